@@ -0,0 +1,8 @@
+-- One row per country, keeps track of when its table was last refreshed
+CREATE TABLE refresh (
+    country_code TEXT NOT NULL PRIMARY KEY,
+    last_attempt TIMESTAMP NOT NULL,
+    last_success TIMESTAMP,
+    last_error TEXT,
+    failures INTEGER NOT NULL DEFAULT 0
+);
@@ -0,0 +1,14 @@
+-- One row per load of a country table, newest row is what is currently in service
+CREATE TABLE dataset (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    country_code TEXT NOT NULL,
+    source TEXT NOT NULL,
+    etag TEXT,
+    last_modified TEXT,
+    sha256 TEXT NOT NULL,
+    rows_loaded INTEGER NOT NULL,
+    rows_rejected INTEGER NOT NULL,
+    loaded_at TIMESTAMP NOT NULL,
+    duration_ms BIGINT NOT NULL
+);
+CREATE INDEX dataset_country_code ON dataset (country_code);
@@ -0,0 +1,2 @@
+-- Path of the archived source file a dataset was loaded from, NULL once it has been pruned
+ALTER TABLE dataset ADD COLUMN archive TEXT;
@@ -0,0 +1,10 @@
+-- What changed in a country table compared to the dataset loaded before it
+CREATE TABLE dataset_change (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    dataset_id INTEGER NOT NULL REFERENCES dataset (id),
+    bank_code TEXT NOT NULL,
+    kind TEXT NOT NULL CHECK (kind IN ('added', 'removed', 'bic_changed', 'name_changed')),
+    old_value TEXT,
+    new_value TEXT
+);
+CREATE INDEX dataset_change_dataset_id ON dataset_change (dataset_id);
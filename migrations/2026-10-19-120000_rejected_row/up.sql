@@ -0,0 +1,9 @@
+-- Rows of a source file that could not be loaded, kept for review when quarantine is on
+CREATE TABLE rejected_row (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    dataset_id INTEGER NOT NULL REFERENCES dataset (id),
+    row_number INTEGER NOT NULL,
+    column_name TEXT,
+    reason TEXT NOT NULL
+);
+CREATE INDEX rejected_row_dataset_id ON rejected_row (dataset_id);
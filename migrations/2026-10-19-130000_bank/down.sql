@@ -0,0 +1,37 @@
+-- This file should undo anything in `up.sql`
+CREATE TABLE t_at (
+    id INTEGER NOT NULL,
+    code TEXT NOT NULL PRIMARY KEY,
+    name TEXT NOT NULL,
+    zip INTEGER NOT NULL,
+    city TEXT NOT NULL,
+    bic TEXT
+);
+CREATE TABLE t_be (
+    id TEXT NOT NULL PRIMARY KEY,
+    name TEXT NOT NULL,
+    bic TEXT NOT NULL
+);
+CREATE TABLE t_de (
+    id INTEGER NOT NULL PRIMARY KEY,
+    code TEXT NOT NULL,
+    name TEXT NOT NULL,
+    zip INTEGER NOT NULL,
+    city TEXT NOT NULL,
+    bic TEXT
+);
+CREATE TABLE t_nl (
+    code TEXT NOT NULL PRIMARY KEY,
+    name TEXT NOT NULL,
+    bic TEXT NOT NULL
+);
+
+-- the ident and record numbers aren't kept in bank, the row order has to do
+INSERT OR IGNORE INTO t_at SELECT id, bank_code, name, coalesce(CAST(postal_code AS INTEGER), 0), coalesce(city, ''), bic
+FROM bank WHERE country_code = 'AT';
+INSERT OR IGNORE INTO t_be SELECT bank_code, name, coalesce(bic, '') FROM bank WHERE country_code = 'BE';
+INSERT INTO t_de SELECT id, bank_code, name, coalesce(CAST(postal_code AS INTEGER), 0), coalesce(city, ''), bic
+FROM bank WHERE country_code = 'DE';
+INSERT OR IGNORE INTO t_nl SELECT bank_code, name, coalesce(bic, '') FROM bank WHERE country_code = 'NL';
+
+DROP TABLE bank;
@@ -0,0 +1,37 @@
+-- One bank directory for all countries instead of a table per country.
+-- flags: 1 = head office, 2 = bank code is being deleted by the publisher
+CREATE TABLE bank (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    country_code TEXT NOT NULL,
+    bank_code TEXT NOT NULL,
+    branch_code TEXT,
+    name TEXT NOT NULL,
+    postal_code TEXT,
+    city TEXT,
+    bic TEXT,
+    flags INTEGER NOT NULL DEFAULT 0,
+    dataset_version INTEGER REFERENCES dataset (id)
+);
+CREATE INDEX bank_country_code_bank_code ON bank (country_code, bank_code);
+CREATE INDEX bank_bic ON bank (bic);
+
+-- zip codes were integers, which lost their leading zeros
+INSERT INTO bank (country_code, bank_code, name, postal_code, city, bic, dataset_version)
+SELECT 'AT', code, name, NULLIF(printf('%04d', zip), '0000'), NULLIF(city, ''), bic,
+    (SELECT max(id) FROM dataset WHERE country_code = 'AT')
+FROM t_at ORDER BY id;
+INSERT INTO bank (country_code, bank_code, name, bic, dataset_version)
+SELECT 'BE', id, name, bic, (SELECT max(id) FROM dataset WHERE country_code = 'BE')
+FROM t_be ORDER BY id;
+INSERT INTO bank (country_code, bank_code, name, postal_code, city, bic, dataset_version)
+SELECT 'DE', code, name, NULLIF(printf('%05d', zip), '00000'), NULLIF(city, ''), bic,
+    (SELECT max(id) FROM dataset WHERE country_code = 'DE')
+FROM t_de ORDER BY id;
+INSERT INTO bank (country_code, bank_code, name, bic, dataset_version)
+SELECT 'NL', code, name, bic, (SELECT max(id) FROM dataset WHERE country_code = 'NL')
+FROM t_nl ORDER BY code;
+
+DROP TABLE t_at;
+DROP TABLE t_be;
+DROP TABLE t_de;
+DROP TABLE t_nl;
@@ -0,0 +1,5 @@
+ALTER TABLE bank DROP COLUMN website;
+ALTER TABLE bank DROP COLUMN phone;
+ALTER TABLE bank DROP COLUMN long_name;
+ALTER TABLE bank DROP COLUMN short_name;
+ALTER TABLE bank DROP COLUMN street;
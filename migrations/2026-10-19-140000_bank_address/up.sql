@@ -0,0 +1,6 @@
+-- address and contact details the sources have but we used to drop
+ALTER TABLE bank ADD COLUMN street TEXT;
+ALTER TABLE bank ADD COLUMN short_name TEXT;
+ALTER TABLE bank ADD COLUMN long_name TEXT;
+ALTER TABLE bank ADD COLUMN phone TEXT;
+ALTER TABLE bank ADD COLUMN website TEXT;
@@ -0,0 +1,3 @@
+ALTER TABLE blacklist DROP COLUMN created_at;
+ALTER TABLE blacklist DROP COLUMN created_by;
+ALTER TABLE blacklist DROP COLUMN reason;
@@ -0,0 +1,4 @@
+-- why, by whom and since when an IBAN is on the blacklist
+ALTER TABLE blacklist ADD COLUMN reason TEXT;
+ALTER TABLE blacklist ADD COLUMN created_by TEXT;
+ALTER TABLE blacklist ADD COLUMN created_at TIMESTAMP;
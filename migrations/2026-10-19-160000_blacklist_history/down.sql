@@ -0,0 +1,3 @@
+DROP TABLE blacklist_history;
+ALTER TABLE blacklist DROP COLUMN expires_at;
+ALTER TABLE blacklist DROP COLUMN category;
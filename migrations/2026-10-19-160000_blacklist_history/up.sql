@@ -0,0 +1,19 @@
+-- category: fraud, closed_account, sanctions or customer_request
+ALTER TABLE blacklist ADD COLUMN category TEXT;
+-- the entry is ignored from then on, NULL for never
+ALTER TABLE blacklist ADD COLUMN expires_at TIMESTAMP;
+-- removed entries used to stay around as blacklisted = 0, the history keeps track of them now
+DELETE FROM blacklist WHERE blacklisted = 0;
+
+CREATE TABLE blacklist_history (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    iban TEXT NOT NULL,
+    -- add or remove
+    action TEXT NOT NULL,
+    reason TEXT,
+    category TEXT,
+    actor TEXT,
+    expires_at TIMESTAMP,
+    changed_at TIMESTAMP NOT NULL
+);
+CREATE INDEX blacklist_history_iban ON blacklist_history (iban);
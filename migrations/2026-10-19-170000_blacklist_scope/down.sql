@@ -0,0 +1,22 @@
+-- rules other than single IBANs are lost
+DROP INDEX blacklist_history_scope_value;
+DROP INDEX blacklist_value;
+DELETE FROM blacklist_history WHERE scope <> 'iban';
+ALTER TABLE blacklist_history RENAME COLUMN value TO iban;
+ALTER TABLE blacklist_history DROP COLUMN scope;
+CREATE INDEX blacklist_history_iban ON blacklist_history (iban);
+
+CREATE TABLE blacklist_iban (
+    iban TEXT NOT NULL PRIMARY KEY,
+    blacklisted BOOLEAN NOT NULL CHECK (blacklisted IN (0, 1)),
+    reason TEXT,
+    created_by TEXT,
+    created_at TIMESTAMP,
+    category TEXT,
+    expires_at TIMESTAMP
+);
+INSERT INTO blacklist_iban
+SELECT value, 1, reason, created_by, created_at, category, expires_at
+FROM blacklist WHERE scope = 'iban';
+DROP TABLE blacklist;
+ALTER TABLE blacklist_iban RENAME TO blacklist;
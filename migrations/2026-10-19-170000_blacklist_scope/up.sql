@@ -0,0 +1,25 @@
+-- Blacklist rules instead of single IBANs. scope is iban, iban_prefix, bank (country code and bank code,
+-- DE10077777), bic or country, value is what the IBAN is matched against.
+CREATE TABLE blacklist_rule (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    scope TEXT NOT NULL,
+    value TEXT NOT NULL,
+    reason TEXT,
+    created_by TEXT,
+    created_at TIMESTAMP,
+    category TEXT,
+    expires_at TIMESTAMP,
+    UNIQUE (scope, value)
+);
+INSERT INTO blacklist_rule (scope, value, reason, created_by, created_at, category, expires_at)
+SELECT 'iban', iban, reason, created_by, created_at, category, expires_at
+FROM blacklist WHERE blacklisted = 1;
+DROP TABLE blacklist;
+ALTER TABLE blacklist_rule RENAME TO blacklist;
+
+ALTER TABLE blacklist_history ADD COLUMN scope TEXT NOT NULL DEFAULT 'iban';
+ALTER TABLE blacklist_history RENAME COLUMN iban TO value;
+DROP INDEX blacklist_history_iban;
+CREATE INDEX blacklist_history_scope_value ON blacklist_history (scope, value);
+-- verify looks rules up by every value that could match the IBAN
+CREATE INDEX blacklist_value ON blacklist (value);
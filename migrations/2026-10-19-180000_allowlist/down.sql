@@ -0,0 +1,3 @@
+DELETE FROM blacklist_history WHERE list <> 'blacklist';
+ALTER TABLE blacklist_history DROP COLUMN list;
+DROP TABLE allowlist;
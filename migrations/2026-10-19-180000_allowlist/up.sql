@@ -0,0 +1,15 @@
+-- Rules that let IBANs through, with the same scopes as blacklist rules
+CREATE TABLE allowlist (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    scope TEXT NOT NULL,
+    value TEXT NOT NULL,
+    reason TEXT,
+    created_by TEXT,
+    created_at TIMESTAMP,
+    expires_at TIMESTAMP,
+    UNIQUE (scope, value)
+);
+CREATE INDEX allowlist_value ON allowlist (value);
+
+-- blacklist or allowlist
+ALTER TABLE blacklist_history ADD COLUMN list TEXT NOT NULL DEFAULT 'blacklist';
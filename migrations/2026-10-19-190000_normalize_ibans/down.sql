@@ -0,0 +1,2 @@
+-- the original spelling of the IBANs is gone, there's nothing to undo
+SELECT 1;
@@ -0,0 +1,82 @@
+-- IBANs are stored in their electronic format, the way iban::normalize has them: without an "IBAN"
+-- label, whitespace, zero width characters, dashes, dots and slashes, in upper case.
+CREATE TEMP TABLE spelling (
+    value TEXT NOT NULL PRIMARY KEY,
+    normalized TEXT
+);
+INSERT INTO spelling (value)
+SELECT value FROM blacklist WHERE scope = 'iban'
+UNION SELECT value FROM allowlist WHERE scope = 'iban'
+UNION SELECT value FROM blacklist_history WHERE scope = 'iban';
+
+WITH RECURSIVE
+    -- char::is_whitespace
+    whitespace (chars) AS (
+        SELECT char(9, 10, 11, 12, 13, 32, 133, 160, 5760, 8192, 8193, 8194, 8195, 8196, 8197, 8198,
+            8199, 8200, 8201, 8202, 8232, 8233, 8239, 8287, 12288)
+    ),
+    -- and U+200B-U+200D, U+2060, U+FEFF, '-', U+2010-U+2015, U+2212, '.' and '/'
+    dropped (chars) AS (
+        SELECT chars || char(8203, 8204, 8205, 8288, 65279, 45, 8208, 8209, 8210, 8211, 8212, 8213,
+            8722, 46, 47)
+        FROM whitespace
+    ),
+    -- "IBAN: ", "IBAN : " and "IBAN " alike
+    unlabelled (value, rest) AS (
+        SELECT value, CASE
+            WHEN UPPER(SUBSTR(LTRIM(value, chars), 1, 4)) = 'IBAN'
+            THEN LTRIM(LTRIM(SUBSTR(LTRIM(value, chars), 5), chars), ':')
+            ELSE value
+        END
+        FROM spelling, whitespace
+    ),
+    -- one character at a time, what isn't dropped is kept
+    walk (value, rest, kept) AS (
+        SELECT value, rest, '' FROM unlabelled
+        UNION ALL
+        SELECT value, SUBSTR(rest, 2), kept || CASE
+            WHEN INSTR((SELECT chars FROM dropped), SUBSTR(rest, 1, 1)) > 0 THEN ''
+            ELSE SUBSTR(rest, 1, 1)
+        END
+        FROM walk WHERE rest != ''
+    )
+UPDATE spelling
+SET normalized = (SELECT UPPER(kept) FROM walk WHERE walk.value = spelling.value AND walk.rest = '');
+
+UPDATE blacklist_history
+SET value = (SELECT normalized FROM spelling WHERE spelling.value = blacklist_history.value)
+WHERE scope = 'iban';
+
+-- Rules that end up on the same IBAN: the newest one (added last) stays, the others are dropped
+-- and show up in the history as removed by this migration.
+CREATE TEMP TABLE newest AS
+SELECT 'blacklist' AS list, MAX(id) AS id
+FROM blacklist JOIN spelling USING (value) WHERE scope = 'iban' GROUP BY normalized
+UNION ALL
+SELECT 'allowlist', MAX(id)
+FROM allowlist JOIN spelling USING (value) WHERE scope = 'iban' GROUP BY normalized;
+
+INSERT INTO blacklist_history (list, scope, value, action, reason, category, actor, expires_at, changed_at)
+SELECT 'blacklist', 'iban', normalized, 'remove', reason, category, 'normalize_ibans migration',
+    expires_at, CURRENT_TIMESTAMP
+FROM blacklist JOIN spelling USING (value)
+WHERE scope = 'iban' AND id NOT IN (SELECT id FROM newest WHERE list = 'blacklist');
+DELETE FROM blacklist
+WHERE scope = 'iban' AND id NOT IN (SELECT id FROM newest WHERE list = 'blacklist');
+UPDATE blacklist
+SET value = (SELECT normalized FROM spelling WHERE spelling.value = blacklist.value)
+WHERE scope = 'iban';
+
+INSERT INTO blacklist_history (list, scope, value, action, reason, actor, expires_at, changed_at)
+SELECT 'allowlist', 'iban', normalized, 'remove', reason, 'normalize_ibans migration', expires_at,
+    CURRENT_TIMESTAMP
+FROM allowlist JOIN spelling USING (value)
+WHERE scope = 'iban' AND id NOT IN (SELECT id FROM newest WHERE list = 'allowlist');
+DELETE FROM allowlist
+WHERE scope = 'iban' AND id NOT IN (SELECT id FROM newest WHERE list = 'allowlist');
+UPDATE allowlist
+SET value = (SELECT normalized FROM spelling WHERE spelling.value = allowlist.value)
+WHERE scope = 'iban';
+
+DROP TABLE newest;
+DROP TABLE spelling;
@@ -0,0 +1 @@
+DROP TABLE iban_key;
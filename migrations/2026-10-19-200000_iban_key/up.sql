@@ -0,0 +1,7 @@
+-- The keys the stored IBANs are hashed with, in the order they were applied.
+-- Only a fingerprint of every key is kept, see privacy::fingerprint.
+CREATE TABLE iban_key (
+    position INTEGER NOT NULL PRIMARY KEY,
+    fingerprint TEXT NOT NULL,
+    rotated_at TIMESTAMP NOT NULL
+);
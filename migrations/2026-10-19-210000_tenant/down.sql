@@ -0,0 +1,38 @@
+-- only the rules of the default tenant are kept
+CREATE TABLE blacklist_rule (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    scope TEXT NOT NULL,
+    value TEXT NOT NULL,
+    reason TEXT,
+    created_by TEXT,
+    created_at TIMESTAMP,
+    category TEXT,
+    expires_at TIMESTAMP,
+    UNIQUE (scope, value)
+);
+INSERT INTO blacklist_rule (id, scope, value, reason, created_by, created_at, category, expires_at)
+SELECT id, scope, value, reason, created_by, created_at, category, expires_at
+FROM blacklist WHERE tenant = '';
+DROP TABLE blacklist;
+ALTER TABLE blacklist_rule RENAME TO blacklist;
+CREATE INDEX blacklist_value ON blacklist (value);
+
+CREATE TABLE allowlist_rule (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    scope TEXT NOT NULL,
+    value TEXT NOT NULL,
+    reason TEXT,
+    created_by TEXT,
+    created_at TIMESTAMP,
+    expires_at TIMESTAMP,
+    UNIQUE (scope, value)
+);
+INSERT INTO allowlist_rule (id, scope, value, reason, created_by, created_at, expires_at)
+SELECT id, scope, value, reason, created_by, created_at, expires_at
+FROM allowlist WHERE tenant = '';
+DROP TABLE allowlist;
+ALTER TABLE allowlist_rule RENAME TO allowlist;
+CREATE INDEX allowlist_value ON allowlist (value);
+
+DELETE FROM blacklist_history WHERE tenant <> '';
+ALTER TABLE blacklist_history DROP COLUMN tenant;
@@ -0,0 +1,38 @@
+-- Every team sharing the server has its own rules, '' is the default tenant that has the rules
+-- from before tenants. The same rule can be on the list of several tenants.
+CREATE TABLE blacklist_tenant (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    scope TEXT NOT NULL,
+    value TEXT NOT NULL,
+    reason TEXT,
+    created_by TEXT,
+    created_at TIMESTAMP,
+    category TEXT,
+    expires_at TIMESTAMP,
+    tenant TEXT NOT NULL DEFAULT '',
+    UNIQUE (tenant, scope, value)
+);
+INSERT INTO blacklist_tenant (id, scope, value, reason, created_by, created_at, category, expires_at)
+SELECT id, scope, value, reason, created_by, created_at, category, expires_at FROM blacklist;
+DROP TABLE blacklist;
+ALTER TABLE blacklist_tenant RENAME TO blacklist;
+CREATE INDEX blacklist_value ON blacklist (value);
+
+CREATE TABLE allowlist_tenant (
+    id INTEGER NOT NULL PRIMARY KEY AUTOINCREMENT,
+    scope TEXT NOT NULL,
+    value TEXT NOT NULL,
+    reason TEXT,
+    created_by TEXT,
+    created_at TIMESTAMP,
+    expires_at TIMESTAMP,
+    tenant TEXT NOT NULL DEFAULT '',
+    UNIQUE (tenant, scope, value)
+);
+INSERT INTO allowlist_tenant (id, scope, value, reason, created_by, created_at, expires_at)
+SELECT id, scope, value, reason, created_by, created_at, expires_at FROM allowlist;
+DROP TABLE allowlist;
+ALTER TABLE allowlist_tenant RENAME TO allowlist;
+CREATE INDEX allowlist_value ON allowlist (value);
+
+ALTER TABLE blacklist_history ADD COLUMN tenant TEXT NOT NULL DEFAULT '';
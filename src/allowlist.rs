@@ -0,0 +1,323 @@
+// Rules that let IBANs through, to exempt an IBAN from a wider blacklist rule or, in allowlist_only
+// mode, to list the only IBANs verify accepts. Rules have the same scopes as blacklist rules.
+use crate::blacklist::{
+    lookup_value, most_specific, parse_op, BlacklistEntry, NewHistory, Rule, Scope, Subject,
+};
+use crate::config;
+use crate::country::schema::{allowlist, blacklist_history};
+use crate::privacy::PrivacyConfig;
+use chrono::{NaiveDateTime, Utc};
+use diesel::{prelude::*, sqlite::SqliteConnection};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    // everything that isn't blacklisted is allowed
+    Open,
+    // only what is on the allowlist is allowed
+    AllowlistOnly,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct AllowlistConfig {
+    pub mode: Mode,
+}
+impl Default for AllowlistConfig {
+    fn default() -> AllowlistConfig {
+        AllowlistConfig { mode: Mode::Open }
+    }
+}
+
+#[derive(Queryable)]
+struct Allowlist {
+    id: i32,
+    scope: String,
+    value: String,
+    reason: Option<String>,
+    created_by: Option<String>,
+    created_at: Option<NaiveDateTime>,
+    expires_at: Option<NaiveDateTime>,
+    // only rules of the tenant are loaded
+    _tenant: String,
+}
+
+#[derive(Insertable)]
+#[table_name = "allowlist"]
+struct NewAllowlist<'a> {
+    tenant: &'a str,
+    scope: &'a str,
+    value: &'a str,
+    reason: Option<&'a str>,
+    created_by: Option<&'a str>,
+    created_at: Option<NaiveDateTime>,
+    expires_at: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, JsonSchema)]
+pub struct AllowlistStatus {
+    pub listed: bool,
+    // the rule that matched
+    pub rule: Option<Rule>,
+    pub reason: Option<String>,
+    // UTC
+    pub since: Option<NaiveDateTime>,
+    pub listed_by: Option<String>,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+// Whether verify lets an IBAN through. When both lists have a matching rule the more specific one
+// decides and the blacklist wins a tie. Without any matching rule the mode decides.
+pub fn allowed(mode: Mode, block: Option<&Rule>, allow: Option<&Rule>) -> bool {
+    match (block, allow) {
+        (Some(block), Some(allow)) => allow.specificity() > block.specificity(),
+        (Some(_), None) => false,
+        (None, Some(_)) => true,
+        (None, None) => mode == Mode::Open,
+    }
+}
+
+// Adding a rule that already exists replaces it. Category and raw of entry don't apply to the
+// allowlist, invalid IBANs are never allowed.
+pub fn allowlist(
+    connection: &SqliteConnection,
+    privacy: &PrivacyConfig,
+    tenant: &str,
+    scope: Scope,
+    value: &str,
+    op: &str,
+    entry: &BlacklistEntry,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let add = parse_op(op)?;
+    let value = if add {
+        privacy.store(scope, value)?
+    } else {
+        privacy.protect(scope, &lookup_value(scope, value)?)
+    };
+    let (reason, created_by) = (entry.reason.as_deref(), entry.created_by.as_deref());
+    let expires_at = entry.expires_at;
+    let now = Utc::now().naive_utc();
+    connection.transaction::<_, diesel::result::Error, _>(|| {
+        if add {
+            diesel::replace_into(allowlist::table)
+                .values(NewAllowlist {
+                    tenant,
+                    scope: scope.as_str(),
+                    value: &value,
+                    reason,
+                    created_by,
+                    created_at: Some(now),
+                    expires_at,
+                })
+                .execute(connection)?;
+        } else {
+            let removed = diesel::delete(
+                allowlist::table
+                    .filter(allowlist::tenant.eq(tenant))
+                    .filter(allowlist::scope.eq(scope.as_str()))
+                    .filter(allowlist::value.eq(&value)),
+            )
+            .execute(connection)?;
+            if removed == 0 {
+                return Ok(());
+            }
+        }
+        diesel::insert_into(blacklist_history::table)
+            .values(NewHistory {
+                tenant,
+                list: "allowlist",
+                scope: scope.as_str(),
+                value: &value,
+                action: if add { "add" } else { "remove" },
+                reason,
+                category: None,
+                actor: created_by,
+                expires_at: if add { expires_at } else { None },
+                changed_at: now,
+            })
+            .execute(connection)?;
+        Ok(())
+    })?;
+    Ok(())
+}
+
+// The most specific rule that matches, expired rules are ignored
+pub fn allowlist_status(
+    connection: &SqliteConnection,
+    privacy: &PrivacyConfig,
+    tenant: &str,
+    subject: &Subject,
+) -> Result<AllowlistStatus, String> {
+    let now = Utc::now().naive_utc();
+    let stored = privacy.protect(Scope::Iban, subject.iban);
+    let rules = allowlist::table
+        .filter(allowlist::tenant.eq(tenant))
+        .filter(allowlist::value.eq_any(subject.candidates(&stored)))
+        .filter(
+            allowlist::expires_at
+                .is_null()
+                .or(allowlist::expires_at.gt(now)),
+        )
+        .load::<Allowlist>(connection)
+        .map_err(|e| e.to_string())?;
+    let matched = most_specific(subject, &stored, rules, |rule| (&rule.scope, &rule.value));
+    Ok(match matched {
+        Some((scope, rule)) => AllowlistStatus {
+            listed: true,
+            rule: Some(Rule {
+                id: rule.id,
+                scope,
+                value: rule.value,
+            }),
+            reason: rule.reason,
+            since: rule.created_at,
+            listed_by: rule.created_by,
+            expires_at: rule.expires_at,
+        },
+        None => AllowlistStatus::default(),
+    })
+}
+
+pub fn mode() -> Result<Mode, String> {
+    Ok(config::load::<AllowlistConfig>("allowlist")?.mode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::establish_connection;
+    use serial_test::serial;
+
+    fn rule(scope: Scope, value: &str) -> Rule {
+        Rule {
+            id: 0,
+            scope,
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        let country = rule(Scope::Country, "DE");
+        // ends before the bank code, so it covers more than the bank
+        let short_prefix = rule(Scope::IbanPrefix, "DE27");
+        let bic8 = rule(Scope::Bic, "NORSDE51");
+        let bic11 = rule(Scope::Bic, "NORSDE51XXX");
+        let bank = rule(Scope::Bank, "DE10077777");
+        let prefix = rule(Scope::IbanPrefix, "DE2710077777");
+        let iban = rule(Scope::Iban, "DE27100777770209299700");
+        // from least to most specific
+        let rules = [
+            None,
+            Some(&country),
+            Some(&short_prefix),
+            Some(&bic8),
+            Some(&bic11),
+            Some(&bank),
+            Some(&prefix),
+            Some(&iban),
+        ];
+        for mode in [Mode::Open, Mode::AllowlistOnly] {
+            for (b, block) in rules.iter().enumerate() {
+                for (a, allow) in rules.iter().enumerate() {
+                    let expected = match (block, allow) {
+                        (None, None) => mode == Mode::Open,
+                        (None, Some(_)) => true,
+                        (Some(_), None) => false,
+                        // the blacklist wins a tie
+                        (Some(_), Some(_)) => a > b,
+                    };
+                    assert_eq!(
+                        allowed(mode, *block, *allow),
+                        expected,
+                        "{:?} block {:?} allow {:?}",
+                        mode,
+                        block,
+                        allow
+                    );
+                }
+            }
+        }
+        // allowing DE27 doesn't let the IBANs of a blacklisted bank through
+        assert!(!allowed(Mode::Open, Some(&bank), Some(&short_prefix)));
+        assert!(allowed(Mode::Open, Some(&short_prefix), Some(&bank)));
+    }
+
+    #[test]
+    #[serial]
+    fn allowlist_rules() {
+        let connection = &establish_connection();
+        let privacy = &PrivacyConfig::default();
+        // rolled back, the rules in the db are left alone
+        connection.test_transaction::<_, String, _>(|| {
+            let subject = Subject {
+                iban: "DE27100777770209299700",
+                bank_code: Some("10077777"),
+                bic: Some("NORSDE51XXX"),
+            };
+            let none = BlacklistEntry::default();
+            let payroll = BlacklistEntry {
+                reason: Some(String::from("payroll")),
+                created_by: Some(String::from("tester")),
+                ..Default::default()
+            };
+            allowlist(connection, privacy, "", Scope::Country, "de", "add", &none).unwrap();
+            allowlist(
+                connection,
+                privacy,
+                "",
+                Scope::Iban,
+                subject.iban,
+                "add",
+                &payroll,
+            )
+            .unwrap();
+            // other tenants have lists of their own
+            let other = allowlist_status(connection, privacy, "payments", &subject).unwrap();
+            let status = allowlist_status(connection, privacy, "", &subject).unwrap();
+            allowlist(
+                connection,
+                privacy,
+                "",
+                Scope::Iban,
+                subject.iban,
+                "remove",
+                &none,
+            )
+            .unwrap();
+            let country = allowlist_status(connection, privacy, "", &subject).unwrap();
+            allowlist(
+                connection,
+                privacy,
+                "",
+                Scope::Country,
+                "DE",
+                "remove",
+                &none,
+            )
+            .unwrap();
+            assert!(!other.listed);
+            assert_eq!(status.rule.map(|rule| rule.scope), Some(Scope::Iban));
+            assert_eq!(status.reason.as_deref(), Some("payroll"));
+            assert_eq!(country.rule.map(|rule| rule.scope), Some(Scope::Country));
+            assert!(
+                !allowlist_status(connection, privacy, "", &subject)
+                    .unwrap()
+                    .listed
+            );
+            assert!(allowlist(
+                connection,
+                privacy,
+                "",
+                Scope::Bank,
+                "10077777",
+                "add",
+                &none
+            )
+            .is_err());
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,126 @@
+// One bank directory for every country, each row tagged with the dataset it was loaded from
+use crate::country::schema::bank;
+use crate::country::BankData;
+use diesel::{prelude::*, sqlite::SqliteConnection};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+// bits of Bank.flags
+pub const HEAD_OFFICE: i32 = 1;
+// the publisher announced the bank code is going away
+pub const DELETION_PENDING: i32 = 2;
+
+#[derive(Queryable, Serialize, Deserialize, Debug, JsonSchema)]
+pub struct Bank {
+    pub id: i32,
+    pub country_code: String,
+    pub bank_code: String,
+    // only for countries that put a branch in the IBAN
+    pub branch_code: Option<String>,
+    pub name: String,
+    pub postal_code: Option<String>,
+    pub city: Option<String>,
+    pub bic: Option<String>,
+    pub flags: i32,
+    pub dataset_version: Option<i32>,
+    pub street: Option<String>,
+    // when the source has a short name (for lists) next to the full one
+    pub short_name: Option<String>,
+    pub long_name: Option<String>,
+    pub phone: Option<String>,
+    pub website: Option<String>,
+}
+impl From<Bank> for BankData {
+    fn from(bank: Bank) -> BankData {
+        BankData {
+            code: bank.bank_code,
+            name: bank.name,
+            short_name: bank.short_name,
+            long_name: bank.long_name,
+            country: bank.country_code,
+            postal_code: bank.postal_code,
+            city: bank.city,
+            street: bank.street,
+            phone: bank.phone,
+            website: bank.website,
+            bic: bank.bic,
+            dataset_version: bank.dataset_version,
+        }
+    }
+}
+
+// What a country parser makes of a row, dataset_version is filled in when it is loaded
+#[derive(Insertable, Debug, Default, Clone, PartialEq)]
+#[table_name = "bank"]
+pub struct NewBank {
+    pub country_code: String,
+    pub bank_code: String,
+    pub branch_code: Option<String>,
+    pub name: String,
+    pub postal_code: Option<String>,
+    pub city: Option<String>,
+    pub bic: Option<String>,
+    pub flags: i32,
+    pub dataset_version: Option<i32>,
+    pub street: Option<String>,
+    // when the source has a short name (for lists) next to the full one
+    pub short_name: Option<String>,
+    pub long_name: Option<String>,
+    pub phone: Option<String>,
+    pub website: Option<String>,
+}
+
+// Swaps the banks of a country for the ones of a new dataset, run it inside a transaction
+pub fn replace_banks(
+    connection: &SqliteConnection,
+    country_code: &str,
+    dataset_version: i32,
+    mut banks: Vec<NewBank>,
+) -> QueryResult<usize> {
+    for bank in &mut banks {
+        bank.dataset_version = Some(dataset_version);
+    }
+    diesel::delete(bank::table.filter(bank::country_code.eq(country_code))).execute(connection)?;
+    // sqlite limits the number of variables in a statement
+    let mut rows = 0;
+    for chunk in banks.chunks(500) {
+        rows += diesel::insert_into(bank::table)
+            .values(chunk)
+            .execute(connection)?;
+    }
+    Ok(rows)
+}
+
+// Some countries list every branch under the bank code, the first row is the bank itself
+pub fn get_bank(
+    connection: &SqliteConnection,
+    country_code: &str,
+    bank_code: &str,
+) -> QueryResult<Option<Bank>> {
+    bank::table
+        .filter(bank::country_code.eq(country_code))
+        .filter(bank::bank_code.eq(bank_code))
+        .order(bank::id.asc())
+        .first::<Bank>(connection)
+        .optional()
+}
+
+pub fn get_banks(connection: &SqliteConnection, country_code: &str) -> QueryResult<Vec<Bank>> {
+    bank::table
+        .filter(bank::country_code.eq(country_code))
+        .order(bank::id.asc())
+        .load::<Bank>(connection)
+}
+
+// Every bank in every country with this BIC. A BIC without branch (8 characters) also finds
+// the branches of the bank.
+pub fn get_banks_by_bic(connection: &SqliteConnection, bic: &str) -> QueryResult<Vec<Bank>> {
+    let bic = bic.trim().to_uppercase();
+    let query = bank::table.order(bank::id.asc()).into_boxed();
+    let query = if bic.len() == 8 {
+        query.filter(bank::bic.like(format!("{}%", bic)))
+    } else {
+        query.filter(bank::bic.eq(bic))
+    };
+    query.load::<Bank>(connection)
+}
@@ -0,0 +1,1036 @@
+// Rules that make verify warn about an IBAN, with why, by whom and until when they apply.
+// A rule covers a single IBAN, an IBAN prefix, a bank, a BIC or a whole country.
+use crate::country::get_country;
+use crate::country::schema::{blacklist, blacklist_history};
+use crate::iban::parse;
+use crate::import::{decode, RowError};
+use crate::privacy::{exported, is_hash, PrivacyConfig};
+use ::iban::IbanLike;
+use chrono::{NaiveDateTime, Utc};
+use diesel::{prelude::*, sqlite::SqliteConnection};
+use rocket::serde::json::serde_json;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::str::FromStr;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Category {
+    Fraud,
+    ClosedAccount,
+    Sanctions,
+    CustomerRequest,
+}
+impl Category {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Category::Fraud => "fraud",
+            Category::ClosedAccount => "closed_account",
+            Category::Sanctions => "sanctions",
+            Category::CustomerRequest => "customer_request",
+        }
+    }
+}
+impl FromStr for Category {
+    type Err = String;
+    fn from_str(category: &str) -> Result<Category, String> {
+        match category.to_lowercase().as_str() {
+            "fraud" => Ok(Category::Fraud),
+            "closed_account" => Ok(Category::ClosedAccount),
+            "sanctions" => Ok(Category::Sanctions),
+            "customer_request" => Ok(Category::CustomerRequest),
+            _ => Err(String::from(
+                "Failure: category must be fraud, closed_account, sanctions or customer_request",
+            )),
+        }
+    }
+}
+
+// From least to most specific, when several rules match an IBAN the most specific one wins,
+// see specificity for where IBAN prefixes go.
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    Country,
+    Bic,
+    // country code followed by bank code, DE10077777
+    Bank,
+    IbanPrefix,
+    Iban,
+}
+impl Scope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Country => "country",
+            Scope::Bic => "bic",
+            Scope::Bank => "bank",
+            Scope::IbanPrefix => "iban_prefix",
+            Scope::Iban => "iban",
+        }
+    }
+
+    // The value as it is stored and matched, upper case without spaces.
+    // IBANs have to pass iban::parse and end up in the electronic format.
+    pub fn normalize(&self, value: &str) -> Result<String, String> {
+        let value = compact(value);
+        if *self == Scope::Iban {
+            return parse(&value)
+                .map(|iban| iban.electronic_str().to_string())
+                .map_err(|e| format!("Failure: {} is not a valid IBAN: {}", value, e));
+        }
+        let alphanumeric = value.chars().all(|c| c.is_ascii_alphanumeric());
+        let country = value.len() >= 2 && value[..2].chars().all(|c| c.is_ascii_uppercase());
+        let ok = alphanumeric
+            && match self {
+                Scope::Country => country && value.len() == 2,
+                Scope::Bic => value.len() == 8 || value.len() == 11,
+                Scope::Bank | Scope::IbanPrefix => country && value.len() > 2,
+                Scope::Iban => false,
+            };
+        if ok {
+            Ok(value)
+        } else {
+            Err(format!(
+                "Failure: {} is not a valid value for a {} rule",
+                value,
+                self.as_str()
+            ))
+        }
+    }
+}
+// Upper case without whitespace, what is left of a value that isn't valid
+fn compact(value: &str) -> String {
+    value
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_uppercase()
+}
+
+// For removing rules and looking them up, which has to work for IBANs that were added raw
+pub(crate) fn lookup_value(scope: Scope, value: &str) -> Result<String, String> {
+    match scope {
+        Scope::Iban => Ok(scope.normalize(value).unwrap_or_else(|_| compact(value))),
+        _ => scope.normalize(value),
+    }
+}
+
+impl FromStr for Scope {
+    type Err = String;
+    fn from_str(scope: &str) -> Result<Scope, String> {
+        match scope.to_lowercase().as_str() {
+            "country" => Ok(Scope::Country),
+            "bic" => Ok(Scope::Bic),
+            "bank" => Ok(Scope::Bank),
+            "iban_prefix" => Ok(Scope::IbanPrefix),
+            "iban" => Ok(Scope::Iban),
+            _ => Err(String::from(
+                "Failure: scope must be country, bic, bank, iban_prefix or iban",
+            )),
+        }
+    }
+}
+
+#[derive(Queryable)]
+struct Blacklist {
+    id: i32,
+    scope: String,
+    value: String,
+    reason: Option<String>,
+    created_by: Option<String>,
+    created_at: Option<NaiveDateTime>,
+    category: Option<String>,
+    expires_at: Option<NaiveDateTime>,
+    // only rules of the tenant are loaded
+    _tenant: String,
+}
+
+#[derive(Insertable)]
+#[table_name = "blacklist"]
+struct NewBlacklist<'a> {
+    tenant: &'a str,
+    scope: &'a str,
+    value: &'a str,
+    reason: Option<&'a str>,
+    created_by: Option<&'a str>,
+    created_at: Option<NaiveDateTime>,
+    category: Option<&'a str>,
+    expires_at: Option<NaiveDateTime>,
+}
+
+// What goes with an add or remove, all of it optional
+#[derive(Debug, Default)]
+pub struct BlacklistEntry {
+    pub reason: Option<String>,
+    pub category: Option<Category>,
+    // who added or removed it
+    pub created_by: Option<String>,
+    // UTC, only for add
+    pub expires_at: Option<NaiveDateTime>,
+    // add an IBAN that doesn't pass iban::parse anyway, only spaces are removed
+    pub raw: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+pub struct Rule {
+    pub id: i32,
+    pub scope: Scope,
+    pub value: String,
+}
+impl Rule {
+    // compare these to tell which of two rules is more specific
+    pub fn specificity(&self) -> (u8, usize) {
+        specificity(self.scope, &self.value)
+    }
+}
+
+// An IBAN prefix that ends before the bank code does covers more than a bank, it ranks between
+// country and BIC rules. Longer ones rank above bank rules.
+// Within a scope the longer value wins (an 11 character BIC over its 8 character bank BIC).
+fn specificity(scope: Scope, value: &str) -> (u8, usize) {
+    let bank_end = || {
+        value
+            .get(..2)
+            .and_then(|country_code| get_country(country_code).ok())
+            .map_or(0, |country| 4 + country.bban_layout().bank_code.end)
+    };
+    let rank = match scope {
+        Scope::Country => 0,
+        Scope::IbanPrefix if value.len() < bank_end() => 1,
+        Scope::Bic => 2,
+        Scope::Bank => 3,
+        Scope::IbanPrefix => 4,
+        Scope::Iban => 5,
+    };
+    (rank, value.len())
+}
+
+// What verify tells about the blacklist, the details are only there when the IBAN is listed
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq, JsonSchema)]
+pub struct BlacklistStatus {
+    pub listed: bool,
+    // the rule that matched
+    pub rule: Option<Rule>,
+    pub reason: Option<String>,
+    pub category: Option<Category>,
+    // UTC
+    pub since: Option<NaiveDateTime>,
+    pub listed_by: Option<String>,
+    pub expires_at: Option<NaiveDateTime>,
+}
+
+// What rules are matched against, the bank code and BIC are only known when the bank is
+#[derive(Debug, Default)]
+pub struct Subject<'a> {
+    // electronic format, DE27100777770209299700
+    pub iban: &'a str,
+    pub bank_code: Option<&'a str>,
+    pub bic: Option<&'a str>,
+}
+impl Subject<'_> {
+    fn country_code(&self) -> &str {
+        self.iban.get(..2).unwrap_or_default()
+    }
+
+    fn bank(&self) -> Option<String> {
+        self.bank_code
+            .map(|code| format!("{}{}", self.country_code(), code))
+    }
+
+    // banks publish the head office BIC both with and without XXX
+    fn bic(&self) -> Option<String> {
+        self.bic.map(|bic| match bic.len() {
+            8 => format!("{}XXX", bic),
+            _ => bic.to_string(),
+        })
+    }
+
+    // Every value a rule could have to match, to look them up in one go.
+    // stored is the IBAN as rules on it are stored, see PrivacyConfig::protect.
+    pub(crate) fn candidates(&self, stored: &str) -> Vec<String> {
+        let mut values = (2..=self.iban.len())
+            .filter_map(|end| self.iban.get(..end))
+            .map(String::from)
+            .collect::<Vec<_>>();
+        values.push(stored.to_string());
+        values.extend(self.bank());
+        if let Some(bic) = self.bic() {
+            values.push(bic[..bic.len().min(8)].to_string());
+            values.push(bic);
+        }
+        values
+    }
+
+    fn matches(&self, stored: &str, scope: Scope, value: &str) -> bool {
+        match scope {
+            Scope::Country => self.country_code() == value,
+            Scope::Bic => self.bic().is_some_and(|bic| match value.len() {
+                8 => bic.starts_with(value),
+                _ => bic == value,
+            }),
+            Scope::Bank => self.bank().is_some_and(|bank| bank == value),
+            Scope::IbanPrefix => self.iban.starts_with(value),
+            Scope::Iban => stored == value,
+        }
+    }
+}
+
+// The row with the most specific rule that matches the subject, rule gives its scope and value
+pub(crate) fn most_specific<R>(
+    subject: &Subject,
+    stored: &str,
+    rows: Vec<R>,
+    rule: impl Fn(&R) -> (&str, &str),
+) -> Option<(Scope, R)> {
+    rows.into_iter()
+        .filter_map(|row| Some((rule(&row).0.parse::<Scope>().ok()?, row)))
+        .filter(|(scope, row)| subject.matches(stored, *scope, rule(row).1))
+        .max_by_key(|(scope, row)| specificity(*scope, rule(row).1))
+}
+
+pub(crate) fn parse_op(op: &str) -> Result<bool, &'static str> {
+    match op {
+        "ADD" | "Add" | "add" => Ok(true),
+        "REMOVE" | "Remove" | "remove" => Ok(false),
+        _ => Err("Failure: Operation not recognized. Use ADD or REMOVE"),
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "blacklist_history"]
+pub(crate) struct NewHistory<'a> {
+    pub tenant: &'a str,
+    // blacklist or allowlist, the history of both is kept in one table
+    pub list: &'a str,
+    pub scope: &'a str,
+    pub value: &'a str,
+    pub action: &'a str,
+    pub reason: Option<&'a str>,
+    pub category: Option<&'a str>,
+    pub actor: Option<&'a str>,
+    pub expires_at: Option<NaiveDateTime>,
+    pub changed_at: NaiveDateTime,
+}
+
+#[derive(Queryable, Serialize, Deserialize, Debug, JsonSchema)]
+pub struct BlacklistChange {
+    pub id: i32,
+    pub scope: String,
+    pub value: String,
+    // add or remove
+    pub action: String,
+    pub reason: Option<String>,
+    pub category: Option<String>,
+    pub actor: Option<String>,
+    pub expires_at: Option<NaiveDateTime>,
+    pub changed_at: NaiveDateTime,
+}
+
+// Adding a rule that already exists replaces it, tenant is the name of a tenant::Tenant
+pub fn blacklist(
+    connection: &SqliteConnection,
+    privacy: &PrivacyConfig,
+    tenant: &str,
+    scope: Scope,
+    value: &str,
+    op: &str,
+    entry: &BlacklistEntry,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let add = parse_op(op)?;
+    let value = if add && !entry.raw {
+        privacy.store(scope, value)?
+    } else {
+        privacy.protect(scope, &lookup_value(scope, value)?)
+    };
+    let now = Utc::now().naive_utc();
+    let category = entry.category.map(|category| category.as_str());
+    connection.transaction::<_, diesel::result::Error, _>(|| {
+        if add {
+            diesel::replace_into(blacklist::table)
+                .values(NewBlacklist {
+                    tenant,
+                    scope: scope.as_str(),
+                    value: &value,
+                    reason: entry.reason.as_deref(),
+                    created_by: entry.created_by.as_deref(),
+                    created_at: Some(now),
+                    category,
+                    expires_at: entry.expires_at,
+                })
+                .execute(connection)?;
+        } else {
+            let removed = diesel::delete(
+                blacklist::table
+                    .filter(blacklist::tenant.eq(tenant))
+                    .filter(blacklist::scope.eq(scope.as_str()))
+                    .filter(blacklist::value.eq(&value)),
+            )
+            .execute(connection)?;
+            // there was no such rule, so nothing happened to it either
+            if removed == 0 {
+                return Ok(());
+            }
+        }
+        diesel::insert_into(blacklist_history::table)
+            .values(NewHistory {
+                tenant,
+                list: "blacklist",
+                scope: scope.as_str(),
+                value: &value,
+                action: if add { "add" } else { "remove" },
+                reason: entry.reason.as_deref(),
+                category,
+                actor: entry.created_by.as_deref(),
+                expires_at: if add { entry.expires_at } else { None },
+                changed_at: now,
+            })
+            .execute(connection)?;
+        Ok(())
+    })?;
+    Ok(())
+}
+
+// The most specific rule that matches, expired rules are ignored
+pub fn blacklist_status(
+    connection: &SqliteConnection,
+    privacy: &PrivacyConfig,
+    tenant: &str,
+    subject: &Subject,
+) -> Result<BlacklistStatus, String> {
+    let now = Utc::now().naive_utc();
+    let stored = privacy.protect(Scope::Iban, subject.iban);
+    let rules = blacklist::table
+        .filter(blacklist::tenant.eq(tenant))
+        .filter(blacklist::value.eq_any(subject.candidates(&stored)))
+        .filter(
+            blacklist::expires_at
+                .is_null()
+                .or(blacklist::expires_at.gt(now)),
+        )
+        .load::<Blacklist>(connection)
+        .map_err(|e| e.to_string())?;
+    let matched = most_specific(subject, &stored, rules, |rule| (&rule.scope, &rule.value));
+    Ok(match matched {
+        Some((scope, rule)) => BlacklistStatus {
+            listed: true,
+            rule: Some(Rule {
+                id: rule.id,
+                scope,
+                value: rule.value,
+            }),
+            reason: rule.reason,
+            category: rule.category.and_then(|category| category.parse().ok()),
+            since: rule.created_at,
+            listed_by: rule.created_by,
+            expires_at: rule.expires_at,
+        },
+        None => BlacklistStatus::default(),
+    })
+}
+
+// Every add and remove of a rule of list (blacklist or allowlist), oldest first
+pub fn get_history(
+    connection: &SqliteConnection,
+    privacy: &PrivacyConfig,
+    tenant: &str,
+    list: &str,
+    scope: Scope,
+    value: &str,
+) -> Result<Vec<BlacklistChange>, String> {
+    let value = privacy.protect(scope, &lookup_value(scope, value)?);
+    blacklist_history::table
+        .filter(blacklist_history::tenant.eq(tenant))
+        .filter(blacklist_history::list.eq(list))
+        .filter(blacklist_history::scope.eq(scope.as_str()))
+        .filter(blacklist_history::value.eq(value))
+        .order(blacklist_history::id.asc())
+        .select((
+            blacklist_history::id,
+            blacklist_history::scope,
+            blacklist_history::value,
+            blacklist_history::action,
+            blacklist_history::reason,
+            blacklist_history::category,
+            blacklist_history::actor,
+            blacklist_history::expires_at,
+            blacklist_history::changed_at,
+        ))
+        .load::<BlacklistChange>(connection)
+        .map_err(|e| e.to_string())
+}
+
+// What a bulk import does with the rules that aren't in the file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportMode {
+    // they are removed, the file is the new blacklist
+    Replace,
+    // they are kept, rules in the file replace the ones with the same scope and value
+    Merge,
+}
+impl FromStr for ImportMode {
+    type Err = String;
+    fn from_str(mode: &str) -> Result<ImportMode, String> {
+        match mode.to_lowercase().as_str() {
+            "replace" => Ok(ImportMode::Replace),
+            "merge" => Ok(ImportMode::Merge),
+            _ => Err(String::from("Failure: mode must be replace or merge")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Csv,
+    Json,
+}
+impl FromStr for Format {
+    type Err = String;
+    fn from_str(format: &str) -> Result<Format, String> {
+        match format.to_lowercase().as_str() {
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            _ => Err(String::from("Failure: format must be csv or json")),
+        }
+    }
+}
+
+// A rule as it is exported and imported, a row of the csv or an object of the json array.
+// Only value (or iban) is required, the scope defaults to iban.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+pub struct BlacklistRecord {
+    #[serde(default)]
+    pub scope: Option<Scope>,
+    #[serde(alias = "iban")]
+    pub value: String,
+    #[serde(default)]
+    pub reason: Option<String>,
+    #[serde(default)]
+    pub category: Option<Category>,
+    #[serde(default)]
+    pub created_by: Option<String>,
+    // UTC, like 2026-10-19T07:39:22, now when it is missing
+    #[serde(default)]
+    pub created_at: Option<NaiveDateTime>,
+    #[serde(default)]
+    pub expires_at: Option<NaiveDateTime>,
+    // added raw (see BlacklistEntry), the value is imported as it is instead of checked
+    #[serde(default)]
+    pub raw: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, JsonSchema)]
+pub struct BlacklistImport {
+    pub rows_imported: usize,
+    // rules dropped by a replace
+    pub rules_removed: usize,
+    // when there are any nothing was imported
+    pub errors: Vec<RowError>,
+}
+
+// Row number and the record, or why it couldn't be read
+pub type RecordRow = (usize, Result<BlacklistRecord, String>);
+
+// The rows of a csv (with header) or json (array of objects) file
+pub fn read_records(bytes: &[u8], format: Format) -> Result<Vec<RecordRow>, String> {
+    match format {
+        Format::Csv => {
+            let text = decode(bytes, "utf-8")?;
+            let mut reader = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(text.as_bytes());
+            Ok(reader
+                .deserialize::<BlacklistRecord>()
+                .enumerate()
+                // the header is row 1
+                .map(|(i, record)| (i + 2, record.map_err(|e| e.to_string())))
+                .collect())
+        }
+        Format::Json => {
+            let values = serde_json::from_slice::<Vec<serde_json::Value>>(bytes)
+                .map_err(|e| format!("Failure: not a json array: {}", e))?;
+            Ok(values
+                .into_iter()
+                .enumerate()
+                .map(|(i, value)| {
+                    (
+                        i + 1,
+                        serde_json::from_value(value).map_err(|e| e.to_string()),
+                    )
+                })
+                .collect())
+        }
+    }
+}
+
+pub fn write_records(records: &[BlacklistRecord], format: Format) -> Result<String, String> {
+    match format {
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            for record in records {
+                writer.serialize(record).map_err(|e| e.to_string())?;
+            }
+            let bytes = writer.into_inner().map_err(|e| e.to_string())?;
+            String::from_utf8(bytes).map_err(|e| e.to_string())
+        }
+        Format::Json => serde_json::to_string_pretty(records).map_err(|e| e.to_string()),
+    }
+}
+
+// Every rule of the blacklist, expired ones included, in the order they were added
+pub fn export_blacklist(
+    connection: &SqliteConnection,
+    tenant: &str,
+) -> QueryResult<Vec<BlacklistRecord>> {
+    let rules = blacklist::table
+        .filter(blacklist::tenant.eq(tenant))
+        .order(blacklist::id.asc())
+        .load::<Blacklist>(connection)?;
+    Ok(rules
+        .into_iter()
+        .map(|rule| {
+            let scope = rule.scope.parse().ok();
+            // an IBAN that doesn't come back from normalize as it is was added raw
+            let raw = scope == Some(Scope::Iban)
+                && !is_hash(&rule.value)
+                && Scope::Iban.normalize(&rule.value).as_ref() != Ok(&rule.value);
+            BlacklistRecord {
+                value: scope.map_or(rule.value.clone(), |scope| exported(scope, &rule.value)),
+                scope,
+                reason: rule.reason,
+                category: rule.category.and_then(|category| category.parse().ok()),
+                created_by: rule.created_by,
+                created_at: rule.created_at,
+                expires_at: rule.expires_at,
+                raw,
+            }
+        })
+        .collect())
+}
+
+// Loads the rows of read_records in one transaction, every add and remove goes into the history.
+// Nothing changes unless every row is valid, by is the actor for rows without created_by.
+pub fn import_blacklist(
+    connection: &SqliteConnection,
+    privacy: &PrivacyConfig,
+    tenant: &str,
+    rows: Vec<RecordRow>,
+    mode: ImportMode,
+    by: Option<&str>,
+) -> Result<BlacklistImport, String> {
+    let mut import = BlacklistImport::default();
+    let mut seen = HashSet::new();
+    let mut records = Vec::new();
+    for (row, record) in rows {
+        let record = match record {
+            Ok(record) => record,
+            Err(reason) => {
+                import.errors.push(RowError {
+                    row,
+                    column: None,
+                    reason,
+                });
+                continue;
+            }
+        };
+        let scope = record.scope.unwrap_or(Scope::Iban);
+        let reject = |reason: String| RowError {
+            row,
+            column: Some(String::from("value")),
+            reason,
+        };
+        let stored = if record.raw {
+            lookup_value(scope, &record.value).map(|value| privacy.protect(scope, &value))
+        } else {
+            privacy.store(scope, &record.value)
+        };
+        match stored {
+            Ok(value) if !seen.insert((scope, value.clone())) => import.errors.push(reject(
+                format!("duplicate {} rule {}", scope.as_str(), value),
+            )),
+            Ok(value) => records.push((scope, value, record)),
+            Err(e) => import.errors.push(reject(e)),
+        }
+    }
+    if !import.errors.is_empty() {
+        return Ok(import);
+    }
+    let now = Utc::now().naive_utc();
+    connection
+        .transaction::<_, diesel::result::Error, _>(|| {
+            if mode == ImportMode::Replace {
+                let old = blacklist::table
+                    .filter(blacklist::tenant.eq(tenant))
+                    .load::<Blacklist>(connection)?;
+                for rule in &old {
+                    diesel::insert_into(blacklist_history::table)
+                        .values(NewHistory {
+                            tenant,
+                            list: "blacklist",
+                            scope: &rule.scope,
+                            value: &rule.value,
+                            action: "remove",
+                            reason: Some("replaced by an import"),
+                            category: None,
+                            actor: by,
+                            expires_at: None,
+                            changed_at: now,
+                        })
+                        .execute(connection)?;
+                }
+                import.rules_removed =
+                    diesel::delete(blacklist::table.filter(blacklist::tenant.eq(tenant)))
+                        .execute(connection)?;
+            }
+            for (scope, value, record) in &records {
+                let category = record.category.map(|category| category.as_str());
+                let created_by = record.created_by.as_deref().or(by);
+                diesel::replace_into(blacklist::table)
+                    .values(NewBlacklist {
+                        tenant,
+                        scope: scope.as_str(),
+                        value,
+                        reason: record.reason.as_deref(),
+                        created_by,
+                        created_at: Some(record.created_at.unwrap_or(now)),
+                        category,
+                        expires_at: record.expires_at,
+                    })
+                    .execute(connection)?;
+                diesel::insert_into(blacklist_history::table)
+                    .values(NewHistory {
+                        tenant,
+                        list: "blacklist",
+                        scope: scope.as_str(),
+                        value,
+                        action: "add",
+                        reason: record.reason.as_deref(),
+                        category,
+                        actor: created_by,
+                        expires_at: record.expires_at,
+                        changed_at: now,
+                    })
+                    .execute(connection)?;
+            }
+            Ok(())
+        })
+        .map_err(|e| e.to_string())?;
+    import.rows_imported = records.len();
+    Ok(import)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::establish_connection;
+    use chrono::Duration;
+    use serial_test::serial;
+
+    const IBAN: &str = "BE68539007547034";
+
+    #[test]
+    fn parse_category() {
+        assert_eq!("Closed_Account".parse(), Ok(Category::ClosedAccount));
+        assert!("stolen".parse::<Category>().is_err());
+    }
+
+    #[test]
+    fn normalize_values() {
+        assert_eq!(Scope::Country.normalize(" de"), Ok(String::from("DE")));
+        assert_eq!(
+            Scope::Bank.normalize("DE 1007 7777"),
+            Ok(String::from("DE10077777"))
+        );
+        assert_eq!(
+            Scope::Bic.normalize("norsde51"),
+            Ok(String::from("NORSDE51"))
+        );
+        assert!(Scope::Country.normalize("DEU").is_err());
+        assert!(Scope::Bank.normalize("10077777").is_err());
+        assert!(Scope::Bic.normalize("NORSDE5").is_err());
+        assert!(Scope::IbanPrefix.normalize("DE-27").is_err());
+        assert_eq!(
+            Scope::Iban.normalize(" de27 1007 7777 0209 2997 00"),
+            Ok(String::from("DE27100777770209299700"))
+        );
+        assert!(Scope::Iban.normalize("DE27100777770209299704").is_err());
+        assert!(Scope::Iban.normalize("HEJHOPP").is_err());
+        assert_eq!(
+            lookup_value(Scope::Iban, "hej hopp"),
+            Ok(String::from("HEJHOPP"))
+        );
+    }
+
+    #[test]
+    fn match_rules() {
+        let iban = "DE27100777770209299700";
+        let subject = Subject {
+            iban: "DE27100777770209299700",
+            bank_code: Some("10077777"),
+            bic: Some("NORSDE51"),
+        };
+        assert!(subject.matches(iban, Scope::Country, "DE"));
+        assert!(subject.matches(iban, Scope::Bank, "DE10077777"));
+        assert!(subject.matches(iban, Scope::Bic, "NORSDE51"));
+        assert!(subject.matches(iban, Scope::Bic, "NORSDE51XXX"));
+        assert!(!subject.matches(iban, Scope::Bic, "NORSDE51BER"));
+        assert!(subject.matches(iban, Scope::IbanPrefix, "DE2710077777"));
+        assert!(subject.matches(iban, Scope::Iban, "DE27100777770209299700"));
+        // with hashed IBANs only the stored form matches
+        assert!(!subject.matches("C0FFEE", Scope::Iban, "DE27100777770209299700"));
+        assert!(!subject.matches(iban, Scope::Bank, "DE1007777"));
+        assert!(!subject.matches(iban, Scope::Country, "BE"));
+        // without bank data only the IBAN itself can match
+        let unknown = Subject {
+            iban: "DE27100777770209299700",
+            ..Default::default()
+        };
+        assert!(!unknown.matches(iban, Scope::Bank, "DE10077777"));
+        assert!(!unknown.matches(iban, Scope::Bic, "NORSDE51"));
+    }
+
+    #[test]
+    fn prefix_specificity() {
+        let bank = specificity(Scope::Bank, "DE10077777");
+        // DE27 covers IBANs of every German bank
+        assert!(specificity(Scope::IbanPrefix, "DE27") < specificity(Scope::Bic, "NORSDE51"));
+        assert!(specificity(Scope::IbanPrefix, "DE271007") < bank);
+        assert!(specificity(Scope::IbanPrefix, "DE2710077777") > bank);
+        assert!(specificity(Scope::IbanPrefix, "BE68539") > specificity(Scope::Bank, "BE539"));
+        assert!(specificity(Scope::IbanPrefix, "DE27") > specificity(Scope::Country, "DE"));
+    }
+
+    #[test]
+    #[serial]
+    fn most_specific_rule_wins() {
+        let connection = &establish_connection();
+        let privacy = &PrivacyConfig::default();
+        // rolled back, the rules in the db are left alone
+        connection.test_transaction::<_, String, _>(|| {
+            let subject = Subject {
+                iban: IBAN,
+                bank_code: Some("539"),
+                bic: Some("NAPBBE22"),
+            };
+            let rules = [
+                (Scope::Country, "BE"),
+                (Scope::Bic, "NAPBBE22XXX"),
+                (Scope::Bank, "BE539"),
+                (Scope::IbanPrefix, "BE68539"),
+                (Scope::Iban, IBAN),
+            ];
+            let mut matched = Vec::new();
+            for (scope, value) in rules.iter() {
+                blacklist(
+                    connection,
+                    privacy,
+                    "",
+                    *scope,
+                    value,
+                    "add",
+                    &BlacklistEntry::default(),
+                )
+                .unwrap();
+                matched.push(
+                    blacklist_status(connection, privacy, "", &subject)
+                        .unwrap()
+                        .rule,
+                );
+            }
+            for (scope, value) in rules.iter() {
+                blacklist(
+                    connection,
+                    privacy,
+                    "",
+                    *scope,
+                    value,
+                    "remove",
+                    &BlacklistEntry::default(),
+                )
+                .unwrap();
+            }
+            let matched = matched
+                .into_iter()
+                .map(|rule| rule.map(|rule| (rule.scope, rule.value)))
+                .collect::<Vec<_>>();
+            let expected = rules
+                .iter()
+                .map(|(scope, value)| Some((*scope, value.to_string())))
+                .collect::<Vec<_>>();
+            assert_eq!(matched, expected);
+            assert!(
+                !blacklist_status(connection, privacy, "", &subject)
+                    .unwrap()
+                    .listed
+            );
+            Ok(())
+        });
+    }
+
+    #[test]
+    #[serial]
+    fn expiry_and_history() {
+        let connection = &establish_connection();
+        let privacy = &PrivacyConfig::default();
+        // rolled back, the rules in the db are left alone
+        connection.test_transaction::<_, String, _>(|| {
+            let subject = Subject {
+                iban: IBAN,
+                ..Default::default()
+            };
+            let entry = BlacklistEntry {
+                reason: Some(String::from("chargeback")),
+                category: Some(Category::Fraud),
+                created_by: Some(String::from("tester")),
+                expires_at: Some(Utc::now().naive_utc() - Duration::days(1)),
+                raw: false,
+            };
+            let before = get_history(connection, privacy, "", "blacklist", Scope::Iban, IBAN)
+                .unwrap()
+                .len();
+            blacklist(connection, privacy, "", Scope::Iban, IBAN, "add", &entry).unwrap();
+            // already expired
+            let expired = blacklist_status(connection, privacy, "", &subject).unwrap();
+            let entry = BlacklistEntry {
+                expires_at: Some(Utc::now().naive_utc() + Duration::days(1)),
+                ..entry
+            };
+            blacklist(connection, privacy, "", Scope::Iban, IBAN, "add", &entry).unwrap();
+            let listed = blacklist_status(connection, privacy, "", &subject).unwrap();
+            blacklist(
+                connection,
+                privacy,
+                "",
+                Scope::Iban,
+                IBAN,
+                "remove",
+                &BlacklistEntry::default(),
+            )
+            .unwrap();
+            assert!(!expired.listed);
+            assert!(listed.listed);
+            assert_eq!(listed.category, Some(Category::Fraud));
+            assert!(
+                !blacklist_status(connection, privacy, "", &subject)
+                    .unwrap()
+                    .listed
+            );
+
+            let history =
+                get_history(connection, privacy, "", "blacklist", Scope::Iban, IBAN).unwrap();
+            let actions = history[before..]
+                .iter()
+                .map(|change| change.action.as_str())
+                .collect::<Vec<_>>();
+            assert_eq!(actions, ["add", "add", "remove"]);
+            assert_eq!(history[before].actor.as_deref(), Some("tester"));
+            // removing what isn't there leaves no trace
+            blacklist(connection, privacy, "", Scope::Iban, IBAN, "remove", &entry).unwrap();
+            let again =
+                get_history(connection, privacy, "", "blacklist", Scope::Iban, IBAN).unwrap();
+            assert_eq!(again.len(), history.len());
+            let e = blacklist(
+                connection,
+                privacy,
+                "",
+                Scope::Iban,
+                IBAN,
+                "toggle",
+                &BlacklistEntry::default(),
+            );
+            assert!(e.is_err());
+            Ok(())
+        });
+    }
+
+    #[test]
+    #[serial]
+    fn bulk_import_and_export() {
+        let connection = &establish_connection();
+        let privacy = &PrivacyConfig::default();
+        // rolled back, the rules in the db are left alone
+        connection.test_transaction::<_, String, _>(|| {
+            diesel::delete(blacklist::table)
+                .execute(connection)
+                .unwrap();
+            let csv = "iban,reason,category\n\
+            DE27 1007 7777 0209 2997 00,chargeback,fraud\n\
+            BE68539007547034,,\n\
+            HEJHOPP,,\n\
+            BE68539007547034,again,\n\
+            DE44500105175407324931,,stolen\n";
+            let rows = read_records(csv.as_bytes(), Format::Csv).unwrap();
+            let import =
+                import_blacklist(connection, privacy, "", rows, ImportMode::Merge, None).unwrap();
+            let rows = import.errors.iter().map(|e| e.row).collect::<Vec<_>>();
+            assert_eq!(rows, [4, 5, 6]);
+            assert_eq!(import.rows_imported, 0);
+            assert!(export_blacklist(connection, "").unwrap().is_empty());
+
+            let json = r#"[
+            {"iban": "DE27 1007 7777 0209 2997 00", "reason": "chargeback", "category": "fraud"},
+            {"scope": "bank", "value": "BE539", "created_by": "fraud team"}
+        ]"#;
+            let rows = read_records(json.as_bytes(), Format::Json).unwrap();
+            let import = import_blacklist(
+                connection,
+                privacy,
+                "",
+                rows,
+                ImportMode::Merge,
+                Some("tester"),
+            )
+            .unwrap();
+            assert_eq!(import.rows_imported, 2);
+            let exported = export_blacklist(connection, "").unwrap();
+            assert_eq!(exported[0].value, "DE27100777770209299700");
+            assert_eq!(exported[0].created_by.as_deref(), Some("tester"));
+            assert_eq!(exported[1].created_by.as_deref(), Some("fraud team"));
+
+            // what is exported can be imported again
+            let csv = write_records(&exported[..1], Format::Csv).unwrap();
+            let rows = read_records(csv.as_bytes(), Format::Csv).unwrap();
+            let import =
+                import_blacklist(connection, privacy, "", rows, ImportMode::Replace, None).unwrap();
+            assert_eq!((import.rows_imported, import.rules_removed), (1, 2));
+            let replaced = export_blacklist(connection, "").unwrap();
+            assert_eq!(replaced[0].value, exported[0].value);
+            assert_eq!(replaced[0].created_at, exported[0].created_at);
+            assert_eq!(replaced[0].category, Some(Category::Fraud));
+
+            // the whole export, a raw IBAN included, comes back the same
+            blacklist(
+                connection,
+                privacy,
+                "",
+                Scope::Iban,
+                "HEJHOPP",
+                "add",
+                &BlacklistEntry {
+                    raw: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+            let exported = export_blacklist(connection, "").unwrap();
+            assert!(exported[1].raw && !exported[0].raw);
+            for format in [Format::Csv, Format::Json] {
+                let written = write_records(&exported, format).unwrap();
+                let rows = read_records(written.as_bytes(), format).unwrap();
+                let import =
+                    import_blacklist(connection, privacy, "", rows, ImportMode::Replace, None)
+                        .unwrap();
+                assert!(import.errors.is_empty(), "{:?}", import.errors);
+                assert_eq!(export_blacklist(connection, "").unwrap(), exported);
+            }
+
+            let rows = read_records(b"[]", Format::Json).unwrap();
+            import_blacklist(connection, privacy, "", rows, ImportMode::Replace, None).unwrap();
+            assert!(export_blacklist(connection, "").unwrap().is_empty());
+            assert!(read_records(b"{}", Format::Json).is_err());
+            Ok(())
+        });
+    }
+}
@@ -0,0 +1,241 @@
+// What changed between two loads of a country table, kept for the master data team to review
+use crate::country::schema::{dataset, dataset_change};
+use crate::country::BankData;
+use chrono::NaiveDateTime;
+use diesel::{prelude::*, sqlite::SqliteConnection};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    BicChanged,
+    NameChanged,
+}
+impl ChangeKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ChangeKind::Added => "added",
+            ChangeKind::Removed => "removed",
+            ChangeKind::BicChanged => "bic_changed",
+            ChangeKind::NameChanged => "name_changed",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, JsonSchema)]
+pub struct Change {
+    pub bank_code: String,
+    pub kind: ChangeKind,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+}
+
+// A change together with the load it came with
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct DatasetChange {
+    pub dataset_id: i32,
+    pub loaded_at: NaiveDateTime,
+    pub bank_code: String,
+    pub kind: String,
+    pub old_value: Option<String>,
+    pub new_value: Option<String>,
+}
+
+#[derive(Insertable)]
+#[table_name = "dataset_change"]
+struct NewDatasetChange<'a> {
+    dataset_id: i32,
+    bank_code: &'a str,
+    kind: &'a str,
+    old_value: Option<&'a str>,
+    new_value: Option<&'a str>,
+}
+
+// Some countries have several rows per bank code (branches), the first one is what verify returns
+fn by_code(bank_data: Vec<BankData>) -> BTreeMap<String, BankData> {
+    let mut map = BTreeMap::new();
+    for bd in bank_data {
+        map.entry(bd.code.clone()).or_insert(bd);
+    }
+    map
+}
+
+pub fn diff(before: Vec<BankData>, after: Vec<BankData>) -> Vec<Change> {
+    let before = by_code(before);
+    let after = by_code(after);
+    let mut changes = Vec::new();
+    for (code, old) in &before {
+        match after.get(code) {
+            None => changes.push(Change {
+                bank_code: code.clone(),
+                kind: ChangeKind::Removed,
+                old_value: Some(old.name.clone()),
+                new_value: None,
+            }),
+            Some(new) => {
+                if old.bic != new.bic {
+                    changes.push(Change {
+                        bank_code: code.clone(),
+                        kind: ChangeKind::BicChanged,
+                        old_value: old.bic.clone(),
+                        new_value: new.bic.clone(),
+                    });
+                }
+                if old.name != new.name {
+                    changes.push(Change {
+                        bank_code: code.clone(),
+                        kind: ChangeKind::NameChanged,
+                        old_value: Some(old.name.clone()),
+                        new_value: Some(new.name.clone()),
+                    });
+                }
+            }
+        }
+    }
+    for (code, new) in after {
+        if !before.contains_key(&code) {
+            changes.push(Change {
+                bank_code: code,
+                kind: ChangeKind::Added,
+                old_value: None,
+                new_value: Some(new.name),
+            });
+        }
+    }
+    changes
+}
+
+pub fn record_changes(
+    connection: &SqliteConnection,
+    dataset_id: i32,
+    changes: &[Change],
+) -> QueryResult<usize> {
+    let rows = changes
+        .iter()
+        .map(|change| NewDatasetChange {
+            dataset_id,
+            bank_code: &change.bank_code,
+            kind: change.kind.as_str(),
+            old_value: change.old_value.as_deref(),
+            new_value: change.new_value.as_deref(),
+        })
+        .collect::<Vec<_>>();
+    diesel::insert_into(dataset_change::table)
+        .values(&rows)
+        .execute(connection)
+}
+
+// All changes of a country in datasets loaded at or after since, oldest first
+pub fn get_changes(
+    connection: &SqliteConnection,
+    country_code: &str,
+    since: Option<NaiveDateTime>,
+) -> QueryResult<Vec<DatasetChange>> {
+    let mut query = dataset_change::table
+        .inner_join(dataset::table)
+        .filter(dataset::country_code.eq(country_code.to_uppercase()))
+        .into_boxed();
+    if let Some(since) = since {
+        query = query.filter(dataset::loaded_at.ge(since));
+    }
+    let rows = query
+        .order(dataset_change::id.asc())
+        .select((
+            dataset_change::dataset_id,
+            dataset::loaded_at,
+            dataset_change::bank_code,
+            dataset_change::kind,
+            dataset_change::old_value,
+            dataset_change::new_value,
+        ))
+        .load::<(
+            i32,
+            NaiveDateTime,
+            String,
+            String,
+            Option<String>,
+            Option<String>,
+        )>(connection)?;
+    Ok(rows
+        .into_iter()
+        .map(
+            |(dataset_id, loaded_at, bank_code, kind, old_value, new_value)| DatasetChange {
+                dataset_id,
+                loaded_at,
+                bank_code,
+                kind,
+                old_value,
+                new_value,
+            },
+        )
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bank(code: &str, name: &str, bic: Option<&str>) -> BankData {
+        BankData {
+            code: code.to_string(),
+            name: name.to_string(),
+            short_name: None,
+            long_name: None,
+            country: String::from("DE"),
+            postal_code: None,
+            city: None,
+            street: None,
+            phone: None,
+            website: None,
+            bic: bic.map(String::from),
+            dataset_version: None,
+        }
+    }
+
+    #[test]
+    fn diff_bank_data() {
+        let before = vec![
+            bank("100", "Kept Bank", Some("KEPTDEFF")),
+            bank("200", "Old Name", Some("NAMEDEFF")),
+            bank("300", "Moving Bank", Some("OLDBDEFF")),
+            bank("400", "Closed Bank", None),
+        ];
+        let after = vec![
+            bank("100", "Kept Bank", Some("KEPTDEFF")),
+            bank("200", "New Name", Some("NAMEDEFF")),
+            bank("300", "Moving Bank", Some("NEWBDEFF")),
+            bank("500", "Opened Bank", None),
+            bank("500", "Opened Bank Branch", None),
+        ];
+        let changes = diff(before, after);
+        assert_eq!(changes.len(), 4);
+        assert!(changes.contains(&Change {
+            bank_code: "200".into(),
+            kind: ChangeKind::NameChanged,
+            old_value: Some("Old Name".into()),
+            new_value: Some("New Name".into()),
+        }));
+        assert!(changes.contains(&Change {
+            bank_code: "300".into(),
+            kind: ChangeKind::BicChanged,
+            old_value: Some("OLDBDEFF".into()),
+            new_value: Some("NEWBDEFF".into()),
+        }));
+        assert!(changes.contains(&Change {
+            bank_code: "400".into(),
+            kind: ChangeKind::Removed,
+            old_value: Some("Closed Bank".into()),
+            new_value: None,
+        }));
+        assert!(changes.contains(&Change {
+            bank_code: "500".into(),
+            kind: ChangeKind::Added,
+            old_value: None,
+            new_value: Some("Opened Bank".into()),
+        }));
+    }
+}
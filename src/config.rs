@@ -0,0 +1,20 @@
+// Our settings live next to rocket's in Rocket.toml, every feature reads its own section
+use rocket::figment::Figment;
+use serde::de::DeserializeOwned;
+
+pub fn from_figment<T: DeserializeOwned + Default>(
+    figment: &Figment,
+    section: &str,
+) -> Result<T, String> {
+    if figment.find_value(section).is_err() {
+        return Ok(T::default());
+    }
+    figment
+        .extract_inner::<T>(section)
+        .map_err(|e| format!("Failure: bad [{}] config: {}", section, e))
+}
+
+// Reads Rocket.toml the same way the server does, for use outside of a running rocket
+pub fn load<T: DeserializeOwned + Default>(section: &str) -> Result<T, String> {
+    from_figment(&rocket::Config::figment(), section)
+}
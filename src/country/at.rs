@@ -1,122 +1,165 @@
 // Austria
-use super::schema::t_at;
-use super::{Country, Db};
+use super::{BbanLayout, Country, Db};
+use crate::bank::{NewBank, HEAD_OFFICE};
+use crate::config;
+use crate::download::{Source, CSV_CONTENT_TYPES};
+use crate::import::{decode, find_header, missing, nfc, optional, ImportConfig, Parsed};
 use csv;
-use curl::easy::Easy;
-use diesel::{prelude::*, sqlite::SqliteConnection};
-use serde::{Deserialize, Serialize};
-use std::env;
+use serde::Deserialize;
+use std::collections::HashSet;
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::Read;
 
-#[derive(Debug, Serialize, Deserialize, Insertable, Queryable)]
-#[table_name = "t_at"]
+#[derive(Debug, Deserialize)]
 pub struct BankData {
-    #[serde(rename = "Identnummer")]
-    id: i32, // Ident numbers arent unique in austria, could remove this field
+    // Hauptanstalt for the bank itself, Zweigstelle for its branches
+    #[serde(rename = "Kennzeichen")]
+    kind: String,
     #[serde(rename = "Bankleitzahl")]
-    code: String, // iban is unique in this db, using as primary key
+    code: String,
     #[serde(rename = "Bankenname")]
     name: String,
+    #[serde(rename = "Straße")]
+    street: String,
+    // not always a number, some branches abroad have foreign postal codes
     #[serde(rename = "PLZ")]
-    zip: i32,
+    zip: String,
     #[serde(rename = "Ort")]
     city: String,
+    #[serde(rename = "Telefon")]
+    phone: String,
     #[serde(rename = "SWIFT-Code")]
     bic: Option<String>,
-}
-impl From<BankData> for super::BankData {
-    fn from(bank_data: BankData) -> super::BankData {
-        super::BankData {
-            code: bank_data.code,
-            name: bank_data.name.clone(),
-            zip: bank_data.zip,
-            city: bank_data.city.clone(),
-            bic: bank_data.bic,
-        }
-    }
+    #[serde(rename = "Homepage")]
+    website: String,
 }
 
-fn create_entry(connection: &SqliteConnection, bank_data: Vec<BankData>) {
-    diesel::insert_into(t_at::table)
-        .values(&bank_data)
-        .execute(connection)
-        .expect("Error inserting new task"); // crash on failure is correct here
-}
-fn download_data() -> Result<(), curl::Error> {
-    let path = format!(
-        "{}/at-data-download.csv",
-        env::var("IBAN_BEAVER_RESOURCES").unwrap_or_else(|_| "./resources".into())
-    );
-    if let Ok(mut file) = File::create(&path) {
-        let mut easy = Easy::new();
-        easy.url("https://www.oenb.at/docroot/downloads_observ/sepa-zv-vz_gesamt.csv")?;
-        easy.follow_location(true)?;
-        easy.write_function(move |data| {
-            file.write_all(data).unwrap();
-            Ok(data.len())
-        })
-        .unwrap();
-        easy.perform()?;
+const SOURCE: Source = Source {
+    url: "https://www.oenb.at/docroot/downloads_observ/sepa-zv-vz_gesamt.csv",
+    file_name: "at-data-download.csv",
+    user_agent: None,
+    content_types: CSV_CONTENT_TYPES,
+    // the OeNB says ISO-8859-1, windows-1252 is the same plus € and curly quotes
+    charset: Some("windows-1252"),
+};
+// found by name, below a preamble that changes with every release
+const COLUMNS: [&str; 9] = [
+    "Kennzeichen",
+    "Bankleitzahl",
+    "Bankenname",
+    "Straße",
+    "PLZ",
+    "Ort",
+    "Telefon",
+    "SWIFT-Code",
+    "Homepage",
+];
+// Column and reason of a deserialize error, without the position csv puts in front of it
+fn row_error<'a>(headers: &'a csv::StringRecord, e: &csv::Error) -> (Option<&'a str>, String) {
+    match e.kind() {
+        csv::ErrorKind::Deserialize { err, .. } => (
+            err.field().and_then(|field| headers.get(field as usize)),
+            err.kind().to_string(),
+        ),
+        _ => (None, e.to_string()),
     }
-
-    Ok(())
 }
 pub struct At {}
 impl Db for At {
-    fn get_bank_data(
-        &self,
-        connection: &SqliteConnection,
-        bank_code: &str,
-    ) -> Result<super::BankData, String> {
-        use super::schema::t_at::dsl::*;
-        let data = t_at
-            .filter(code.eq(bank_code))
-            .limit(1)
-            .load::<BankData>(connection)
-            .expect("Error loading posts")
-            .pop();
-
-        match data {
-            Some(d) => Ok(super::BankData::from(d)),
-            None => Err(String::from("")),
-        }
+    fn source(&self) -> &'static Source {
+        &SOURCE
     }
 
-    fn fill_table(&self, connection: &SqliteConnection) -> Result<(), Box<dyn std::error::Error>> {
+    fn parse(&self, path: &str) -> Result<Parsed, Box<dyn std::error::Error>> {
         // --- parse csv ---
-        let path = format!(
-            "{}/at-data-download.csv",
-            env::var("IBAN_BEAVER_RESOURCES").unwrap_or_else(|_| "./resources".into())
-        );
-        // drop table if it exists already
-        diesel::delete(t_at::table).execute(connection).unwrap();
-
-        // Fancy footwork to deal with ISO-8859-1 to UTF-8
         let mut file = File::open(path)?;
         let mut buf: Vec<u8> = Vec::new();
         file.read_to_end(&mut buf)?;
-        // The skip is to skip the garbage above header. Very fragile. Should maybe do it some other way...
-        let utf8_csv = buf.iter().map(|&c| c as char).skip(512).collect::<String>();
+        let config = config::load::<ImportConfig>("import")?;
+        let charset = config.charset(self.country_code(), SOURCE.charset.unwrap_or("utf-8"));
+        let utf8_csv = decode(&buf, charset)?;
 
-        let mut rdr = csv::ReaderBuilder::new()
+        // there's a preamble above the header, so csv can't tell which line is the header
+        let records = csv::ReaderBuilder::new()
             .delimiter(b';')
             .flexible(true)
-            .from_reader(utf8_csv.as_bytes());
-        // we shouldnt unwrap result, but w/e
-        let bank_data = rdr.deserialize().map(|result| { let bd: BankData = result.unwrap(); bd}).collect::<Vec<BankData>>();
-        create_entry(connection, bank_data);
-
-        Ok(())
+            .has_headers(false)
+            .from_reader(utf8_csv.as_bytes())
+            .into_records()
+            .collect::<Vec<_>>();
+        let (header, _) = find_header(
+            records.iter().map(|record| match record {
+                Ok(record) => record.iter().map(String::from).collect(),
+                Err(_) => Vec::new(),
+            }),
+            &COLUMNS,
+        )?;
+        let headers = records[header].as_ref().map_err(|e| e.to_string())?.clone();
+        let mut parsed = Parsed::default();
+        let mut seen = HashSet::new();
+        for (i, record) in records.into_iter().enumerate().skip(header + 1) {
+            // one record per line, csv's own line numbers are off with \r\n
+            let row = i + 1;
+            let record = match record {
+                Ok(record) => record,
+                Err(e) => {
+                    parsed.report.reject(row, None, e);
+                    continue;
+                }
+            };
+            if record.iter().all(|field| field.trim().is_empty()) {
+                continue;
+            }
+            match record.deserialize::<BankData>(Some(&headers)) {
+                Ok(bd) => {
+                    let name = nfc(&bd.name);
+                    if let Some(column) =
+                        missing(&[("Bankleitzahl", &bd.code), ("Bankenname", &name)])
+                    {
+                        parsed.report.reject(row, Some(column), "value is missing");
+                    } else if !seen.insert(bd.code.clone()) {
+                        parsed
+                            .report
+                            .reject(row, Some("Bankleitzahl"), "duplicate bank code");
+                    } else {
+                        parsed.banks.push(NewBank {
+                            country_code: self.country_code().to_string(),
+                            bank_code: bd.code,
+                            name,
+                            postal_code: optional(&bd.zip),
+                            city: optional(&bd.city),
+                            street: optional(&bd.street),
+                            phone: optional(&bd.phone),
+                            website: optional(&bd.website),
+                            bic: bd.bic,
+                            flags: if bd.kind.trim() == "Hauptanstalt" {
+                                HEAD_OFFICE
+                            } else {
+                                0
+                            },
+                            ..Default::default()
+                        });
+                    }
+                }
+                Err(e) => {
+                    let (column, reason) = row_error(&headers, &e);
+                    parsed.report.reject(row, column, reason);
+                }
+            }
+        }
+        Ok(parsed)
     }
-
-    fn update_table(
-        &self,
-        connection: &SqliteConnection,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        download_data()?;
-        self.fill_table(connection)?;
-        Ok(())
+}
+impl Country for At {
+    fn country_code(&self) -> &'static str {
+        "AT"
+    }
+    // 5 digit bank code, 11 digit account number
+    fn bban_layout(&self) -> BbanLayout {
+        BbanLayout {
+            bank_code: 0..5,
+            account_number: 5..16,
+            check_digits: None,
+        }
     }
 }
-impl Country for At {}
@@ -1,117 +1,146 @@
 // Germany
-use super::schema::t_de;
-use crate::{country::Country, db::Db};
-use calamine::{open_workbook, RangeDeserializerBuilder, Reader, Xlsx};
-use curl::easy::Easy;
-use diesel::{prelude::*, sqlite::SqliteConnection};
-use serde::{Deserialize, Serialize};
-use std::env;
-use std::fs::File;
-use std::io::Write;
+use crate::bank::{NewBank, DELETION_PENDING, HEAD_OFFICE};
+use crate::country::{BbanLayout, Country};
+use crate::db::Db;
+use crate::download::{Source, XLSX_CONTENT_TYPES};
+use crate::import::{cells, find_header, missing, nfc, optional, postal_code, Parsed};
+use calamine::{open_workbook, DataType, DeError, Range, RangeDeserializerBuilder, Reader, Xlsx};
+use serde::Deserialize;
+use std::collections::HashSet;
 
-#[derive(Debug, Serialize, Deserialize, Insertable, Queryable)]
-#[table_name = "t_de"]
+#[derive(Debug, Deserialize)]
 pub struct BankData {
     #[serde(rename = "Datensatz-nummer")]
     id: i32,
     #[serde(rename = "Bank-leitzahl")]
     code: String,
+    // 1 for the bank itself, 2 for its branches
+    #[serde(rename = "Merkmal")]
+    kind: i32,
     #[serde(rename = "Kurzbezeichnung")]
     name: String,
+    #[serde(rename = "Bezeichnung")]
+    long_name: String,
     #[serde(rename = "PLZ")]
-    zip: i32,
+    zip: DataType,
     #[serde(rename = "Ort")]
     city: String,
     #[serde(rename = "BIC")]
     bic: Option<String>,
+    #[serde(rename = "Bankleitzahl-löschung")]
+    deleted: i32,
 }
-impl From<BankData> for super::BankData {
-    fn from(bank_data: BankData) -> super::BankData {
-        super::BankData {
-            code: bank_data.code,
-            name: bank_data.name.clone(),
-            zip: bank_data.zip,
-            city: bank_data.city.clone(),
-            bic: bank_data.bic,
-        }
-    }
-}
-fn create_entry(connection: &SqliteConnection, bank_data: Vec<BankData>) {
-    diesel::insert_into(t_de::table)
-        .values(&bank_data)
-        .execute(connection)
-        .expect("Error inserting new task"); // crash on failure is correct here
-}
-fn download_data() -> Result<(), curl::Error> {
-    let path = format!(
-        "{}/de-data-download.xlsx",
-        env::var("IBAN_BEAVER_RESOURCES").unwrap_or_else(|_| "./resources".into())
-    );
-    if let Ok(mut file) = File::create(&path) {
-        let mut easy = Easy::new();
-        easy.url("https://www.bundesbank.de/resource/blob/602630/38698577eac2fb9d6fe2265bbbeacdd5/mL/blz-aktuell-xls-data.xlsx")?;
-        easy.follow_location(true)?;
-        easy.write_function(move |data| {
-            file.write_all(data).unwrap();
-            Ok(data.len())
-        })
-        .unwrap();
-        easy.perform()?;
-    }
-
-    Ok(())
+const SOURCE: Source = Source {
+    url: "https://www.bundesbank.de/resource/blob/602630/38698577eac2fb9d6fe2265bbbeacdd5/mL/blz-aktuell-xls-data.xlsx",
+    file_name: "de-data-download.xlsx",
+    user_agent: None,
+    content_types: XLSX_CONTENT_TYPES,
+    charset: None,
+};
+const COLUMNS: [&str; 9] = [
+    "Datensatz-nummer",
+    "Bank-leitzahl",
+    "Merkmal",
+    "Kurzbezeichnung",
+    "Bezeichnung",
+    "PLZ",
+    "Ort",
+    "BIC",
+    "Bankleitzahl-löschung",
+];
+// Header of the cell a deserialize error is about, calamine only knows it for some errors
+fn error_column(range: &Range<DataType>, e: &DeError) -> Option<String> {
+    let column = match e {
+        DeError::CellError { pos, .. } | DeError::UnexpectedEndOfRow { pos } => pos.1,
+        _ => return None,
+    };
+    let header_row = range.start()?.0;
+    range
+        .get_value((header_row, column))
+        .map(|header| header.to_string())
 }
 pub struct De {}
 impl Db for De {
-    fn get_bank_data(
-        &self,
-        connection: &SqliteConnection,
-        bank_code: &str,
-    ) -> Result<super::BankData, String> {
-        use super::schema::t_de::dsl::*;
-        let data = t_de
-            .filter(code.eq(bank_code))
-            .limit(1)
-            .load::<BankData>(connection)
-            .expect("Error loading posts")
-            .pop();
-
-        match data {
-            Some(d) => Ok(super::BankData::from(d)),
-            None => Err(String::from("")),
-        }
+    fn source(&self) -> &'static Source {
+        &SOURCE
     }
 
-    fn fill_table(&self, connection: &SqliteConnection) -> Result<(), Box<dyn std::error::Error>> {
+    fn parse(&self, path: &str) -> Result<Parsed, Box<dyn std::error::Error>> {
         // --- parse xml ---
 
-        let path = format!(
-            "{}/de-data-download.xlsx",
-            env::var("IBAN_BEAVER_RESOURCES").unwrap_or_else(|_| "./resources".into())
-        );
-        // drop table if it exists already
-        diesel::delete(t_de::table).execute(connection).unwrap();
-
         let mut workbook: Xlsx<_> = open_workbook(path)?;
 
-        let range = workbook
+        let sheet = workbook
             .worksheet_range("Daten")
             .ok_or(calamine::Error::Msg("Cannot find 'Daten'"))??;
-        let iter = RangeDeserializerBuilder::new().from_range(&range)?;
+        // the columns are mapped by their serde names, starting at whatever row has all of them
+        let (header, _) = find_header(sheet.rows().map(cells), &COLUMNS)?;
+        let (start, end) = sheet
+            .start()
+            .zip(sheet.end())
+            .ok_or(calamine::Error::Msg("Sheet is empty"))?;
+        let header_row = start.0 as usize + header;
+        let range = sheet.range((header_row as u32, start.1), end);
+        let iter = RangeDeserializerBuilder::new().from_range::<_, BankData>(&range)?;
 
-        // put in db
-        let bank_data = iter.map(|result| result.unwrap()).collect::<Vec<BankData>>();
-        create_entry(connection, bank_data);
-        Ok(())
+        let mut parsed = Parsed::default();
+        let mut seen = HashSet::new();
+        for (i, result) in iter.enumerate() {
+            // calamine counts from 0 and the header comes first
+            let row = header_row + i + 2;
+            match result {
+                Ok(bd) => {
+                    let name = nfc(&bd.name);
+                    if let Some(column) =
+                        missing(&[("Bank-leitzahl", &bd.code), ("Kurzbezeichnung", &name)])
+                    {
+                        parsed.report.reject(row, Some(column), "value is missing");
+                    } else if !seen.insert(bd.id) {
+                        parsed.report.reject(
+                            row,
+                            Some("Datensatz-nummer"),
+                            "duplicate record number",
+                        );
+                    } else {
+                        let mut flags = 0;
+                        if bd.kind == 1 {
+                            flags |= HEAD_OFFICE;
+                        }
+                        if bd.deleted == 1 {
+                            flags |= DELETION_PENDING;
+                        }
+                        parsed.banks.push(NewBank {
+                            country_code: self.country_code().to_string(),
+                            bank_code: bd.code,
+                            short_name: Some(name.clone()),
+                            long_name: optional(&bd.long_name),
+                            name,
+                            postal_code: postal_code(&bd.zip, 5),
+                            city: optional(&bd.city),
+                            bic: bd.bic,
+                            flags,
+                            ..Default::default()
+                        });
+                    }
+                }
+                Err(e) => parsed
+                    .report
+                    .reject(row, error_column(&range, &e).as_deref(), e),
+            }
+        }
+        Ok(parsed)
     }
-
-    fn update_table(
-        &self,
-        connection: &SqliteConnection,
-    ) -> Result<(), Box<dyn std::error::Error>> {
-        download_data()?;
-        self.fill_table(connection)?;
-        Ok(())
+}
+impl Country for De {
+    fn country_code(&self) -> &'static str {
+        "DE"
+    }
+    // 8 digit bank code, 10 digit account number with a check digit that depends on the bank
+    fn bban_layout(&self) -> BbanLayout {
+        BbanLayout {
+            bank_code: 0..8,
+            account_number: 8..18,
+            check_digits: None,
+        }
     }
 }
-impl Country for De {}
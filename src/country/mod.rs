@@ -1,7 +1,11 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use crate::bank::{get_bank, get_banks};
 use crate::db::Db;
+use crate::download::{fetcher, Download, DownloadError};
+use diesel::sqlite::SqliteConnection;
+use std::ops::Range;
 //use crate::iban::Iban;
 
 pub mod at;
@@ -14,13 +18,65 @@ pub mod schema;
 pub struct BankData {
     pub code: String,
     pub name: String,
-    pub zip: i32,
-    pub city: String,
+    // only for sources that have both, name is one of them
+    pub short_name: Option<String>,
+    pub long_name: Option<String>,
+    // country code
+    pub country: String,
+    // a string, leading zeros matter and not every country uses digits only
+    pub postal_code: Option<String>,
+    pub city: Option<String>,
+    pub street: Option<String>,
+    pub phone: Option<String>,
+    pub website: Option<String>,
     pub bic: Option<String>,
+    // id of the dataset the data was loaded from, see /countries/<cc>/dataset
+    pub dataset_version: Option<i32>,
 }
 
+// every country that get_country knows about, keep the two in sync (see tests)
+pub const SUPPORTED_COUNTRIES: [&str; 4] = ["AT", "BE", "DE", "NL"];
+
 //pub trait Country: Iban + Db {}
-pub trait Country: Db {}
+// Where the parts of the BBAN are, as positions in the BBAN
+#[derive(Debug, Clone, PartialEq)]
+pub struct BbanLayout {
+    pub bank_code: Range<usize>,
+    pub account_number: Range<usize>,
+    pub check_digits: Option<Range<usize>>,
+}
+
+pub trait Country: Db {
+    fn country_code(&self) -> &'static str;
+    fn bban_layout(&self) -> BbanLayout;
+    // National check digits that cover the whole BBAN, most countries only have bank specific ones
+    fn national_check(&self, _bban: &str) -> bool {
+        true
+    }
+    fn get_bank_data(
+        &self,
+        connection: &SqliteConnection,
+        bank_code: &str,
+    ) -> Result<BankData, String> {
+        get_bank(connection, self.country_code(), bank_code)
+            .map_err(|e| e.to_string())?
+            .map(BankData::from)
+            .ok_or_else(String::new)
+    }
+    fn get_all_bank_data(&self, connection: &SqliteConnection) -> Result<Vec<BankData>, String> {
+        let banks = get_banks(connection, self.country_code()).map_err(|e| e.to_string())?;
+        Ok(banks.into_iter().map(BankData::from).collect())
+    }
+    // Puts the file at path, Ok(None) when it hasn't changed since the previous download.
+    // Where it comes from is configured in the [sources] section of Rocket.toml
+    fn download_data(
+        &self,
+        path: &str,
+        previous: Option<&Download>,
+    ) -> Result<Option<Download>, DownloadError> {
+        fetcher(self.country_code(), self.source())?.fetch(path, previous)
+    }
+}
 
 pub fn get_country(country_code: &str) -> Result<Box<dyn Country>, String> {
     match country_code {
@@ -31,3 +87,28 @@ pub fn get_country(country_code: &str) -> Result<Box<dyn Country>, String> {
         _ => Err(String::from("Failure: Country specified is not supported.")),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn supported_countries() {
+        for country_code in SUPPORTED_COUNTRIES {
+            let country = get_country(country_code).unwrap();
+            assert_eq!(country.country_code(), country_code);
+        }
+        // and no country that get_country knows is missing from the list
+        for first in 'A'..='Z' {
+            for second in 'A'..='Z' {
+                let country_code = format!("{}{}", first, second);
+                assert_eq!(
+                    get_country(&country_code).is_ok(),
+                    SUPPORTED_COUNTRIES.contains(&country_code.as_str()),
+                    "{}",
+                    country_code
+                );
+            }
+        }
+    }
+}
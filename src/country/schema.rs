@@ -1,46 +1,133 @@
 table! {
-    blacklist (iban) {
-        iban -> Text,
-        blacklisted -> Bool,
+    allowlist (id) {
+        id -> Integer,
+        scope -> Text,
+        value -> Text,
+        reason -> Nullable<Text>,
+        created_by -> Nullable<Text>,
+        created_at -> Nullable<Timestamp>,
+        expires_at -> Nullable<Timestamp>,
+        tenant -> Text,
     }
 }
 
 table! {
-    t_at (code) {
+    bank (id) {
         id -> Integer,
-        code -> Text,
+        country_code -> Text,
+        bank_code -> Text,
+        branch_code -> Nullable<Text>,
         name -> Text,
-        zip -> Integer,
-        city -> Text,
+        postal_code -> Nullable<Text>,
+        city -> Nullable<Text>,
         bic -> Nullable<Text>,
+        flags -> Integer,
+        dataset_version -> Nullable<Integer>,
+        street -> Nullable<Text>,
+        short_name -> Nullable<Text>,
+        long_name -> Nullable<Text>,
+        phone -> Nullable<Text>,
+        website -> Nullable<Text>,
     }
 }
 
 table! {
-    t_be (id) {
-        id -> Text,
-        name -> Text,
-        bic -> Text,
+    blacklist (id) {
+        id -> Integer,
+        scope -> Text,
+        value -> Text,
+        reason -> Nullable<Text>,
+        created_by -> Nullable<Text>,
+        created_at -> Nullable<Timestamp>,
+        category -> Nullable<Text>,
+        expires_at -> Nullable<Timestamp>,
+        tenant -> Text,
     }
 }
 
 table! {
-    t_de (id) {
+    blacklist_history (id) {
         id -> Integer,
-        code -> Text,
-        name -> Text,
-        zip -> Integer,
-        city -> Text,
-        bic -> Nullable<Text>,
+        value -> Text,
+        action -> Text,
+        reason -> Nullable<Text>,
+        category -> Nullable<Text>,
+        actor -> Nullable<Text>,
+        expires_at -> Nullable<Timestamp>,
+        changed_at -> Timestamp,
+        scope -> Text,
+        list -> Text,
+        tenant -> Text,
     }
 }
 
 table! {
-    t_nl (code) {
-        code -> Text,
-        name -> Text,
-        bic -> Text,
+    dataset (id) {
+        id -> Integer,
+        country_code -> Text,
+        source -> Text,
+        etag -> Nullable<Text>,
+        last_modified -> Nullable<Text>,
+        sha256 -> Text,
+        rows_loaded -> Integer,
+        rows_rejected -> Integer,
+        loaded_at -> Timestamp,
+        duration_ms -> BigInt,
+        archive -> Nullable<Text>,
+    }
+}
+
+table! {
+    dataset_change (id) {
+        id -> Integer,
+        dataset_id -> Integer,
+        bank_code -> Text,
+        kind -> Text,
+        old_value -> Nullable<Text>,
+        new_value -> Nullable<Text>,
+    }
+}
+
+table! {
+    iban_key (position) {
+        position -> Integer,
+        fingerprint -> Text,
+        rotated_at -> Timestamp,
+    }
+}
+
+table! {
+    refresh (country_code) {
+        country_code -> Text,
+        last_attempt -> Timestamp,
+        last_success -> Nullable<Timestamp>,
+        last_error -> Nullable<Text>,
+        failures -> Integer,
     }
 }
 
-allow_tables_to_appear_in_same_query!(blacklist, t_at, t_be, t_de, t_nl,);
+table! {
+    rejected_row (id) {
+        id -> Integer,
+        dataset_id -> Integer,
+        row_number -> Integer,
+        column_name -> Nullable<Text>,
+        reason -> Text,
+    }
+}
+
+joinable!(bank -> dataset (dataset_version));
+joinable!(dataset_change -> dataset (dataset_id));
+joinable!(rejected_row -> dataset (dataset_id));
+
+allow_tables_to_appear_in_same_query!(
+    allowlist,
+    bank,
+    blacklist,
+    blacklist_history,
+    dataset,
+    dataset_change,
+    iban_key,
+    refresh,
+    rejected_row,
+);
@@ -0,0 +1,353 @@
+// Bookkeeping of every load of a country table, with archived source files to roll back to
+use crate::bank::{get_banks, replace_banks};
+use crate::changes::{diff, record_changes};
+use crate::config;
+use crate::country::schema::dataset;
+use crate::country::{get_country, BankData, Country};
+use crate::db::{load_file, resource_path};
+use crate::download::{unique_path, Download};
+use crate::import::{record_rejected, ImportConfig, ImportReport, Parsed};
+use chrono::{NaiveDateTime, Utc};
+use diesel::{prelude::*, sqlite::SqliteConnection};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use std::time::Instant;
+
+#[derive(Queryable, Serialize, Deserialize, Debug, JsonSchema)]
+pub struct Dataset {
+    pub id: i32,
+    pub country_code: String,
+    pub source: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub sha256: String,
+    pub rows_loaded: i32,
+    pub rows_rejected: i32,
+    pub loaded_at: NaiveDateTime,
+    pub duration_ms: i64,
+    // archived copy of the source file, None once it has been pruned
+    pub archive: Option<String>,
+}
+impl From<&Dataset> for Download {
+    fn from(dataset: &Dataset) -> Download {
+        Download {
+            source: dataset.source.clone(),
+            etag: dataset.etag.clone(),
+            last_modified: dataset.last_modified.clone(),
+        }
+    }
+}
+#[derive(Insertable)]
+#[table_name = "dataset"]
+struct NewDataset {
+    country_code: String,
+    source: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    sha256: String,
+    rows_loaded: i32,
+    rows_rejected: i32,
+    loaded_at: NaiveDateTime,
+    duration_ms: i64,
+}
+
+// A dataset together with the rows that didn't make it in
+#[derive(Debug)]
+pub struct Load {
+    pub dataset: Dataset,
+    pub report: ImportReport,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct HistoryConfig {
+    // number of source files kept per country that can be rolled back to
+    pub keep: usize,
+}
+impl Default for HistoryConfig {
+    fn default() -> HistoryConfig {
+        HistoryConfig { keep: 5 }
+    }
+}
+
+// Fill the bank table of a country from the file at path and keep a record of what was loaded.
+// Without a download the file itself is recorded as the source.
+pub fn load_table(
+    connection: &SqliteConnection,
+    country: &dyn Country,
+    path: &str,
+    download: Option<Download>,
+) -> Result<Load, Box<dyn std::error::Error>> {
+    let start = Instant::now();
+    let mut hasher = Sha256::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    let Parsed { banks, mut report } = country.parse(path)?;
+    report.check_budget(banks.len())?;
+    if banks.is_empty() {
+        return Err("Failure: no bank data found, keeping the current table".into());
+    }
+    report.rows_loaded = banks.len();
+    let download = download.unwrap_or(Download {
+        source: path.to_string(),
+        ..Default::default()
+    });
+    let new_dataset = NewDataset {
+        country_code: country.country_code().to_string(),
+        source: download.source,
+        etag: download.etag,
+        last_modified: download.last_modified,
+        sha256: format!("{:x}", hasher.finalize()),
+        rows_loaded: report.rows_loaded as i32,
+        rows_rejected: report.rows_rejected as i32,
+        loaded_at: Utc::now().naive_utc(),
+        duration_ms: start.elapsed().as_millis() as i64,
+    };
+    let quarantine = config::load::<ImportConfig>("import")?.quarantine;
+    // lookups keep seeing the old banks until the new ones are in, together with their dataset,
+    // the rejected rows and what changed. Inserting the dataset first takes the write lock, so
+    // no other load gets between the banks we compare with and the ones we replace.
+    let country_code = country.country_code();
+    let loaded = connection.transaction::<_, diesel::result::Error, _>(|| {
+        diesel::insert_into(dataset::table)
+            .values(&new_dataset)
+            .execute(connection)?;
+        let loaded = dataset::table
+            .filter(dataset::country_code.eq(country_code))
+            .order(dataset::id.desc())
+            .first::<Dataset>(connection)?;
+        let before = bank_data(connection, country_code)?;
+        replace_banks(connection, country_code, loaded.id, banks)?;
+        if !report.errors.is_empty() && quarantine {
+            record_rejected(connection, loaded.id, &report.errors)?;
+        }
+        // a first load would list every bank as added, that's not worth keeping
+        if !before.is_empty() {
+            let changes = diff(before, bank_data(connection, country_code)?);
+            if !changes.is_empty() {
+                record_changes(connection, loaded.id, &changes)?;
+            }
+        }
+        Ok(loaded)
+    })?;
+
+    // the table is already loaded, a failing archive shouldn't fail the load
+    if let Err(e) = archive(connection, &loaded, path, &country.data_file()) {
+        eprintln!("Failed to archive dataset {}: {}", loaded.id, e);
+    }
+    // with its archive, the load has gone through either way
+    let dataset = get_dataset(connection, country_code)
+        .ok()
+        .flatten()
+        .unwrap_or(loaded);
+    Ok(Load { dataset, report })
+}
+
+fn bank_data(connection: &SqliteConnection, country_code: &str) -> QueryResult<Vec<BankData>> {
+    Ok(get_banks(connection, country_code)?
+        .into_iter()
+        .map(BankData::from)
+        .collect())
+}
+
+fn archive(
+    connection: &SqliteConnection,
+    loaded: &Dataset,
+    path: &str,
+    data_file: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = resource_path(&format!("archive/{}", loaded.country_code.to_lowercase()));
+    fs::create_dir_all(&dir)?;
+    let file_name = Path::new(data_file)
+        .file_name()
+        .ok_or("Failure: data file has no name")?
+        .to_string_lossy();
+    let archive_path = format!("{}/{}-{}", dir, loaded.id, file_name);
+    fs::copy(path, &archive_path)?;
+    diesel::update(dataset::table.find(loaded.id))
+        .set(dataset::archive.eq(&archive_path))
+        .execute(connection)?;
+
+    let config = config::load::<HistoryConfig>("history")?;
+    let archived = dataset::table
+        .filter(dataset::country_code.eq(&loaded.country_code))
+        .filter(dataset::archive.is_not_null())
+        .order(dataset::id.desc())
+        .load::<Dataset>(connection)?;
+    for dataset in archived.into_iter().skip(config.keep) {
+        if let Some(archive) = &dataset.archive {
+            let _ = fs::remove_file(archive);
+        }
+        diesel::update(dataset::table.find(dataset.id))
+            .set(dataset::archive.eq(None::<String>))
+            .execute(connection)?;
+    }
+    Ok(())
+}
+
+// Load the archived source file of an earlier dataset again, it becomes the data file
+pub fn rollback_table(
+    connection: &SqliteConnection,
+    country_code: &str,
+    dataset_id: i32,
+) -> Result<Load, Box<dyn std::error::Error>> {
+    let country = get_country(country_code)?;
+    let previous = dataset::table
+        .find(dataset_id)
+        .filter(dataset::country_code.eq(country.country_code()))
+        .first::<Dataset>(connection)
+        .optional()?
+        .ok_or("Failure: no such dataset for this country")?;
+    let archive = previous
+        .archive
+        .as_deref()
+        .ok_or("Failure: the source file of this dataset is no longer archived")?;
+    let current = get_dataset(connection, country.country_code())?;
+    let download = rollback_download(&previous, current.as_ref());
+    // the archived file replaces the data file too, or a re-fill would bring back the file that
+    // was rolled back from
+    let path = unique_path(&country.data_file());
+    fs::copy(archive, &path)?;
+    Ok(load_file(connection, country.as_ref(), &path, download)?)
+}
+
+// A rollback takes over the validators of the dataset in service, the next refresh would load
+// the file that was rolled back from again with those of the old dataset. A new file at the
+// source is still loaded.
+fn rollback_download(previous: &Dataset, current: Option<&Dataset>) -> Download {
+    Download {
+        source: format!("rollback to dataset {} ({})", previous.id, previous.source),
+        etag: current.and_then(|current| current.etag.clone()),
+        last_modified: current.and_then(|current| current.last_modified.clone()),
+    }
+}
+
+// The dataset that is currently in service for a country
+pub fn get_dataset(
+    connection: &SqliteConnection,
+    country_code: &str,
+) -> QueryResult<Option<Dataset>> {
+    dataset::table
+        .filter(dataset::country_code.eq(country_code.to_uppercase()))
+        .order(dataset::id.desc())
+        .first::<Dataset>(connection)
+        .optional()
+}
+
+// Every load of a country, newest first
+pub fn get_datasets(
+    connection: &SqliteConnection,
+    country_code: &str,
+) -> QueryResult<Vec<Dataset>> {
+    dataset::table
+        .filter(dataset::country_code.eq(country_code.to_uppercase()))
+        .order(dataset::id.desc())
+        .load::<Dataset>(connection)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::establish_connection;
+    use serial_test::serial;
+    use std::env;
+    use std::ffi::OsString;
+
+    fn dataset(id: i32, etag: &str) -> Dataset {
+        Dataset {
+            id,
+            country_code: String::from("DE"),
+            source: String::from("https://www.bundesbank.de/blz.xlsx"),
+            etag: Some(String::from(etag)),
+            last_modified: Some(format!("modified with {}", etag)),
+            sha256: String::new(),
+            rows_loaded: 1,
+            rows_rejected: 0,
+            loaded_at: Utc::now().naive_utc(),
+            duration_ms: 0,
+            archive: Some(format!("archive/de/{}-blz.xlsx", id)),
+        }
+    }
+
+    #[test]
+    fn rollback_keeps_validators() {
+        let good = dataset(1, "good");
+        let broken = dataset(2, "broken");
+        let download = rollback_download(&good, Some(&broken));
+        assert_eq!(
+            download.source,
+            "rollback to dataset 1 (https://www.bundesbank.de/blz.xlsx)"
+        );
+        // the next refresh asks for anything newer than the broken file
+        assert_eq!(download.etag.as_deref(), Some("broken"));
+        assert_eq!(download.last_modified, broken.last_modified);
+    }
+
+    // A copy of the resources directory with the database in it, loads write data files and
+    // archives there instead of next to the real ones
+    struct Scratch {
+        resources: Option<OsString>,
+        dir: String,
+    }
+    impl Scratch {
+        fn new() -> Scratch {
+            let dir = unique_path(
+                &env::temp_dir()
+                    .join("iban_beaver-resources")
+                    .to_string_lossy(),
+            );
+            fs::create_dir_all(&dir).unwrap();
+            fs::copy(resource_path("db.sqlite3"), format!("{}/db.sqlite3", dir)).unwrap();
+            let resources = env::var_os("IBAN_BEAVER_RESOURCES");
+            env::set_var("IBAN_BEAVER_RESOURCES", &dir);
+            Scratch { resources, dir }
+        }
+    }
+    impl Drop for Scratch {
+        fn drop(&mut self) {
+            match &self.resources {
+                Some(resources) => env::set_var("IBAN_BEAVER_RESOURCES", resources),
+                None => env::remove_var("IBAN_BEAVER_RESOURCES"),
+            }
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn load_at(connection: &SqliteConnection, name: &str) -> Load {
+        let country = get_country("AT").unwrap();
+        let path = unique_path(&country.data_file());
+        fs::write(
+            &path,
+            format!(
+                "Kennzeichen;Bankleitzahl;Bankenname;Straße;PLZ;Ort;Telefon;SWIFT-Code;Homepage\n\
+                 Hauptanstalt;12000;{};Rothschildplatz 1;1020;Wien;;BKAUATWW;\n",
+                name
+            ),
+        )
+        .unwrap();
+        load_file(connection, country.as_ref(), &path, Download::default()).unwrap()
+    }
+
+    fn bank_name(connection: &SqliteConnection) -> String {
+        get_banks(connection, "AT").unwrap().remove(0).name
+    }
+
+    #[test]
+    #[serial]
+    fn refill_after_rollback() {
+        let _scratch = Scratch::new();
+        let connection = &establish_connection();
+        let good = load_at(connection, "UniCredit Bank Austria AG");
+        load_at(connection, "broken");
+        assert_eq!(bank_name(connection), "broken");
+        rollback_table(connection, "AT", good.dataset.id).unwrap();
+        assert_eq!(bank_name(connection), "UniCredit Bank Austria AG");
+        // a re-fill reads the data file, which is the rolled back to file now
+        let country = get_country("AT").unwrap();
+        load_table(connection, country.as_ref(), &country.data_file(), None).unwrap();
+        assert_eq!(bank_name(connection), "UniCredit Bank Austria AG");
+    }
+}
@@ -1,63 +1,217 @@
-use crate::country::schema::blacklist;
-use crate::country::BankData;
-use diesel::{prelude::*, sqlite::SqliteConnection};
+use crate::config;
+use crate::country::schema::refresh;
+use crate::country::{get_country, Country};
+use crate::dataset::{get_dataset, load_table, Load};
+use crate::download::{check_file, unique_path, Download, DownloadConfig, Source};
+use crate::import::Parsed;
+use chrono::{NaiveDateTime, Utc};
+use diesel::{connection::SimpleConnection, prelude::*, sqlite::SqliteConnection};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use std::env;
+use std::fs;
 
-#[derive(Insertable, Queryable)]
-#[table_name = "blacklist"]
-pub struct Blacklist {
-    iban: String,
-    blacklisted: bool,
+#[derive(Insertable, Queryable, Serialize, Deserialize, Debug, JsonSchema)]
+#[table_name = "refresh"]
+pub struct Refresh {
+    pub country_code: String,
+    pub last_attempt: NaiveDateTime,
+    pub last_success: Option<NaiveDateTime>,
+    pub last_error: Option<String>,
+    pub failures: i32,
+}
+
+pub fn resource_path(file_name: &str) -> String {
+    format!(
+        "{}/{}",
+        env::var("IBAN_BEAVER_RESOURCES").unwrap_or_else(|_| "./resources".into()),
+        file_name
+    )
 }
 
 pub fn establish_connection() -> SqliteConnection {
-    let db_path = format!(
-        "{}/db.sqlite3",
-        env::var("IBAN_BEAVER_RESOURCES").unwrap_or_else(|_| "./resources".into())
-    );
-    SqliteConnection::establish(&db_path)
-        .unwrap_or_else(|_| panic!("Error connecting to {}", db_path)) // I could return this error to the user, but should I?
-}
-
-pub fn blacklist(iban: &str, op: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let op = match op {
-        "ADD" | "Add" | "add" => Ok(true),
-        "REMOVE" | "Remove" | "remove" => Ok(false),
-        _ => Err("Failure: Operation not recognized. Use ADD or REMOVE"),
-    }?;
-    let conn = establish_connection();
-    let data = Blacklist {
-        iban: iban.to_string(),
-        blacklisted: op,
+    let db_path = resource_path("db.sqlite3");
+    // I could return this error to the user, but should I?
+    let connection = SqliteConnection::establish(&db_path)
+        .unwrap_or_else(|_| panic!("Error connecting to {}", db_path));
+    // scheduled refreshes write from their own threads, wait for the lock instead of failing
+    connection
+        .batch_execute("PRAGMA busy_timeout = 5000;")
+        .unwrap_or_else(|_| panic!("Error configuring {}", db_path));
+    connection
+}
+
+// Update the table of a country and keep track of how it went in the refresh table.
+// Ok(None) means the source hasn't changed since the last load, so the table was left alone.
+pub fn refresh_table(
+    connection: &SqliteConnection,
+    country_code: &str,
+) -> Result<Option<Load>, String> {
+    let result = download_table(connection, country_code).map_err(String::from);
+    record_refresh(
+        connection,
+        country_code,
+        result.as_ref().map(|_| ()).map_err(String::clone),
+    )
+    .map_err(|e| e.to_string())?;
+    result
+}
+
+// Why a refresh failed. Failed downloads are retried by the fetcher already, see [download].
+#[derive(Debug, PartialEq)]
+pub enum RefreshError {
+    Download(String),
+    // the source file came in but didn't load
+    Load(String),
+}
+impl From<RefreshError> for String {
+    fn from(e: RefreshError) -> String {
+        match e {
+            RefreshError::Download(e) | RefreshError::Load(e) => e,
+        }
+    }
+}
+
+// refresh_table without the refresh table, for the scheduler to record a run once however often
+// it tried
+pub fn download_table(
+    connection: &SqliteConnection,
+    country_code: &str,
+) -> Result<Option<Load>, RefreshError> {
+    let country = get_country(country_code).map_err(RefreshError::Load)?;
+    let previous = get_dataset(connection, country_code)
+        .map_err(|e| RefreshError::Load(e.to_string()))?
+        .map(|dataset| Download::from(&dataset));
+    let path = unique_path(&country.data_file());
+    let result = match country.download_data(&path, previous.as_ref()) {
+        Ok(Some(download)) => load_file(connection, country.as_ref(), &path, download)
+            .map(Some)
+            .map_err(RefreshError::Load),
+        Ok(None) => Ok(None),
+        Err(e) => Err(RefreshError::Download(e.to_string())),
     };
-    diesel::replace_into(blacklist::table)
-        .values(data)
-        .execute(&conn)?;
-    Ok(())
+    let _ = fs::remove_file(&path);
+    result
 }
 
-pub fn is_blacklisted(connection: &SqliteConnection, iban: &str) -> Result<(), String> {
-    use blacklist::dsl::blacklist;
-    let record = blacklist.find(iban).first::<Blacklist>(connection);
-    match record {
-        Ok(data) => {
-            if data.blacklisted {
-                Err(String::from("Failure: IBAN is blacklisted."))
-            } else {
-                Ok(())
+// Load a downloaded or uploaded file from path, next to the data file. It only replaces the data
+// file once the table is loaded, so a re-fill never reads a file that failed to load.
+pub fn load_file(
+    connection: &SqliteConnection,
+    country: &dyn Country,
+    path: &str,
+    download: Download,
+) -> Result<Load, String> {
+    let loaded = load_table(connection, country, path, Some(download));
+    match &loaded {
+        Ok(_) => {
+            if let Err(e) = fs::rename(path, country.data_file()) {
+                // the table is in service already, only a re-fill misses the new file
+                eprintln!("Failed to keep {} as {}: {}", path, country.data_file(), e);
+                let _ = fs::remove_file(path);
             }
         }
-        Err(_) => Ok(()),
+        Err(_) => {
+            let _ = fs::remove_file(path);
+        }
     }
+    loaded.map_err(|e| format!("{:?}", e))
+}
+
+// Load a source file that was brought in by hand, for servers that can't download it.
+// name ends up as the source of the dataset, it is where the file came from.
+pub fn upload_table(
+    connection: &SqliteConnection,
+    country_code: &str,
+    path: &str,
+    name: &str,
+) -> Result<Load, String> {
+    let country = get_country(country_code)?;
+    let config = config::load::<DownloadConfig>("download")?;
+    check_file(country.source(), path, config.max_bytes).map_err(|e| e.to_string())?;
+
+    // a copy of its own, other uploads and refreshes write next to it
+    let upload_path = unique_path(&format!("{}.upload", country.data_file()));
+    fs::copy(path, &upload_path).map_err(|e| e.to_string())?;
+    let download = Download {
+        source: format!("upload of {}", name),
+        ..Default::default()
+    };
+    let load = load_file(connection, country.as_ref(), &upload_path, download)?;
+    record_refresh(connection, country_code, Ok(())).map_err(|e| e.to_string())?;
+    Ok(load)
+}
+
+pub fn record_refresh(
+    connection: &SqliteConnection,
+    country_code: &str,
+    result: Result<(), String>,
+) -> QueryResult<()> {
+    let country_code = country_code.to_uppercase();
+    let now = Utc::now().naive_utc();
+    let previous = refresh::table
+        .find(&country_code)
+        .first::<Refresh>(connection)
+        .optional()?;
+    let record = match result {
+        Ok(()) => Refresh {
+            country_code,
+            last_attempt: now,
+            last_success: Some(now),
+            last_error: None,
+            failures: 0,
+        },
+        Err(e) => Refresh {
+            country_code,
+            last_attempt: now,
+            last_success: previous.as_ref().and_then(|p| p.last_success),
+            last_error: Some(e),
+            failures: previous.map_or(0, |p| p.failures) + 1,
+        },
+    };
+    diesel::replace_into(refresh::table)
+        .values(&record)
+        .execute(connection)?;
+    Ok(())
+}
+
+pub fn get_refreshes(connection: &SqliteConnection) -> QueryResult<Vec<Refresh>> {
+    refresh::table.load::<Refresh>(connection)
 }
 
 pub trait Db {
-    fn get_bank_data(
-        &self,
-        connection: &SqliteConnection,
-        bank_code: &str,
-    ) -> Result<BankData, String>;
-    fn fill_table(&self, connection: &SqliteConnection) -> Result<(), Box<dyn std::error::Error>>;
-    fn update_table(&self, connection: &SqliteConnection)
-        -> Result<(), Box<dyn std::error::Error>>;
+    fn source(&self) -> &'static Source;
+    // the source file of the dataset in service, what a re-fill reads
+    fn data_file(&self) -> String {
+        resource_path(self.source().file_name)
+    }
+    // Reads the banks out of a source file, bad rows are skipped and end up in the report.
+    // load_table puts them in the bank table.
+    fn parse(&self, path: &str) -> Result<Parsed, Box<dyn std::error::Error>>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serial_test::serial;
+
+    #[test]
+    #[serial]
+    fn failed_load_keeps_data_file() {
+        let country = get_country("AT").unwrap();
+        let data_file = country.data_file();
+        let before = fs::read(&data_file).ok();
+        let path = unique_path(&data_file);
+        fs::write(&path, "<html>Please log in</html>").unwrap();
+        let loaded = load_file(
+            &establish_connection(),
+            country.as_ref(),
+            &path,
+            Download::default(),
+        );
+        assert!(loaded.is_err());
+        assert!(fs::metadata(&path).is_err());
+        // a re-fill reads what it read before
+        assert_eq!(fs::read(&data_file).ok(), before);
+    }
 }
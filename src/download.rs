@@ -0,0 +1,589 @@
+// Fetches the source files of the countries. Downloads are configured in the [download] section
+// of Rocket.toml, where files are fetched from in the [sources] section.
+use crate::config;
+use chrono::{DateTime, Utc};
+use curl::easy::{Easy, List};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+pub type DownloadError = Box<dyn Error + Send + Sync>;
+
+// Where a country publishes its data and what we expect to get back, can be overridden in [sources]
+#[derive(Debug)]
+pub struct Source {
+    pub url: &'static str,
+    // name of the file in the resources directory
+    pub file_name: &'static str,
+    pub user_agent: Option<&'static str>,
+    // accepted Content-Type values, compared without parameters like charset
+    pub content_types: &'static [&'static str],
+    // what csv files are decoded with when they have no BOM and aren't UTF-8, xlsx is always UTF-8
+    pub charset: Option<&'static str>,
+}
+
+// Servers are sloppy with these, what we really want to catch is an html error page
+pub const XLSX_CONTENT_TYPES: &[&str] = &[
+    "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+    "application/octet-stream",
+];
+pub const CSV_CONTENT_TYPES: &[&str] = &[
+    "text/csv",
+    "text/comma-separated-values",
+    "text/plain",
+    "application/csv",
+    "application/vnd.ms-excel",
+    "application/octet-stream",
+];
+
+// What we know about a downloaded data file, besides its content
+#[derive(Debug, Default, Clone)]
+pub struct Download {
+    pub source: String,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DownloadConfig {
+    pub retries: u32,
+    // doubled after every failed attempt
+    pub retry_delay_secs: u64,
+    pub timeout_secs: u64,
+    pub max_bytes: u64,
+}
+impl Default for DownloadConfig {
+    fn default() -> DownloadConfig {
+        DownloadConfig {
+            retries: 3,
+            retry_delay_secs: 5,
+            timeout_secs: 300,
+            max_bytes: 50 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct SourcesConfig {
+    // base url of an internal mirror that has every file under its file_name
+    pub mirror: Option<String>,
+    // country code -> url or path of the file, wins over the mirror
+    pub countries: HashMap<String, String>,
+}
+
+pub trait Fetcher {
+    // Puts the file at path. With a previous download the fetch is conditional,
+    // Ok(None) means the file hasn't changed since then and path was left alone.
+    fn fetch(
+        &self,
+        path: &str,
+        previous: Option<&Download>,
+    ) -> Result<Option<Download>, DownloadError>;
+}
+
+// Picks the fetcher for a country: its own entry in [sources.countries], the mirror, or the publisher
+pub fn fetcher(country_code: &str, source: &Source) -> Result<Box<dyn Fetcher>, DownloadError> {
+    let sources = config::load::<SourcesConfig>("sources")?;
+    let download = config::load::<DownloadConfig>("download")?;
+    Ok(fetcher_from(&sources, download, country_code, source))
+}
+
+pub fn fetcher_from(
+    sources: &SourcesConfig,
+    download: DownloadConfig,
+    country_code: &str,
+    source: &Source,
+) -> Box<dyn Fetcher> {
+    let location = location(sources, country_code, source);
+    if location.starts_with("http://") || location.starts_with("https://") {
+        Box::new(HttpFetcher {
+            url: location,
+            user_agent: source.user_agent.map(String::from),
+            content_types: source.content_types,
+            config: download,
+        })
+    } else {
+        let path = location.strip_prefix("file://").unwrap_or(&location);
+        Box::new(FileFetcher {
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+// The url or path a country's file is fetched from
+pub fn location(sources: &SourcesConfig, country_code: &str, source: &Source) -> String {
+    sources
+        .countries
+        .iter()
+        .find(|(code, _)| code.eq_ignore_ascii_case(country_code))
+        .map(|(_, location)| location.clone())
+        .or_else(|| {
+            sources
+                .mirror
+                .as_ref()
+                .map(|mirror| format!("{}/{}", mirror.trim_end_matches('/'), source.file_name))
+        })
+        .unwrap_or_else(|| source.url.to_string())
+}
+
+pub struct HttpFetcher {
+    pub url: String,
+    pub user_agent: Option<String>,
+    pub content_types: &'static [&'static str],
+    pub config: DownloadConfig,
+}
+impl Fetcher for HttpFetcher {
+    fn fetch(
+        &self,
+        path: &str,
+        previous: Option<&Download>,
+    ) -> Result<Option<Download>, DownloadError> {
+        let mut delay = Duration::from_secs(self.config.retry_delay_secs);
+        let mut attempt = 0;
+        loop {
+            match self.try_download(path, previous) {
+                Ok(download) => return Ok(download),
+                Err(Failure::Retry(e)) if attempt < self.config.retries => {
+                    eprintln!(
+                        "Download of {} failed (attempt {}): {}",
+                        self.url,
+                        attempt + 1,
+                        e
+                    );
+                    thread::sleep(delay);
+                    delay *= 2;
+                    attempt += 1;
+                }
+                Err(Failure::Retry(e)) | Err(Failure::Fatal(e)) => return Err(e),
+            }
+        }
+    }
+}
+
+// A file on disk or a network share, for deployments that can't reach the publishers
+pub struct FileFetcher {
+    pub path: PathBuf,
+}
+impl Fetcher for FileFetcher {
+    fn fetch(
+        &self,
+        path: &str,
+        previous: Option<&Download>,
+    ) -> Result<Option<Download>, DownloadError> {
+        let source = self.path.to_string_lossy().to_string();
+        let modified = fs::metadata(&self.path)
+            .map_err(|e| format!("Failure: cannot read {}: {}", source, e))?
+            .modified()?;
+        let last_modified = DateTime::<Utc>::from(modified).to_rfc2822();
+        // by modification time only, after a rollback the source names the dataset rolled back to
+        if previous.and_then(|p| p.last_modified.as_ref()) == Some(&last_modified) {
+            return Ok(None);
+        }
+        let part_path = unique_path(&format!("{}.part", path));
+        if let Err(e) = fs::copy(&self.path, &part_path) {
+            let _ = fs::remove_file(&part_path);
+            return Err(e.into());
+        }
+        fs::rename(&part_path, path)?;
+        Ok(Some(Download {
+            source,
+            etag: None,
+            last_modified: Some(last_modified),
+        }))
+    }
+}
+
+enum Failure {
+    // worth trying again, the network or the server had a bad moment
+    Retry(DownloadError),
+    // trying again will give the same answer
+    Fatal(DownloadError),
+}
+impl From<curl::Error> for Failure {
+    fn from(e: curl::Error) -> Failure {
+        Failure::Retry(e.into())
+    }
+}
+impl From<std::io::Error> for Failure {
+    fn from(e: std::io::Error) -> Failure {
+        Failure::Fatal(e.into())
+    }
+}
+
+impl HttpFetcher {
+    fn try_download(
+        &self,
+        path: &str,
+        previous: Option<&Download>,
+    ) -> Result<Option<Download>, Failure> {
+        // written next to the real file and only moved over it once we know it is good. Every
+        // download has its own, refreshes of the same country can run at the same time.
+        // A failed download starts over, partial files aren't resumed.
+        let part_path = unique_path(&format!("{}.part", path));
+        let result = self.download(&part_path, previous);
+        if !matches!(result, Ok(Some(_))) {
+            let _ = fs::remove_file(&part_path);
+        }
+        let download = result?;
+        if download.is_some() {
+            fs::rename(&part_path, path)?;
+        }
+        Ok(download)
+    }
+
+    fn download(
+        &self,
+        part_path: &str,
+        previous: Option<&Download>,
+    ) -> Result<Option<Download>, Failure> {
+        let mut file = File::create(part_path)?;
+        let mut easy = Easy::new();
+        easy.url(&self.url)?;
+        if let Some(user_agent) = &self.user_agent {
+            easy.useragent(user_agent)?;
+        }
+        easy.follow_location(true)?;
+        easy.timeout(Duration::from_secs(self.config.timeout_secs))?;
+        let mut conditions = List::new();
+        if let Some(etag) = previous.and_then(|p| p.etag.as_ref()) {
+            conditions.append(&format!("If-None-Match: {}", etag))?;
+        }
+        if let Some(last_modified) = previous.and_then(|p| p.last_modified.as_ref()) {
+            conditions.append(&format!("If-Modified-Since: {}", last_modified))?;
+        }
+        easy.http_headers(conditions)?;
+
+        let mut headers = Vec::new();
+        let mut written = 0;
+        let mut write_error = None;
+        let performed = {
+            let mut transfer = easy.transfer();
+            transfer.header_function(|header| {
+                let header = String::from_utf8_lossy(header).trim().to_string();
+                // with redirects we see every response, only keep the headers of the last one
+                if header.starts_with("HTTP/") {
+                    headers.clear();
+                }
+                headers.push(header);
+                true
+            })?;
+            transfer.write_function(|data| {
+                written += data.len() as u64;
+                if written > self.config.max_bytes {
+                    write_error = Some(format!(
+                        "Failure: {} is larger than {} bytes",
+                        self.url, self.config.max_bytes
+                    ));
+                    return Ok(0); // anything short of data.len() aborts the transfer
+                }
+                if let Err(e) = file.write_all(data) {
+                    write_error = Some(e.to_string());
+                    return Ok(0);
+                }
+                Ok(data.len())
+            })?;
+            transfer.perform()
+        };
+        if let Some(e) = write_error {
+            return Err(Failure::Fatal(e.into()));
+        }
+        performed?;
+
+        let status = easy.response_code()?;
+        if status == 304 && previous.is_some() {
+            return Ok(None);
+        }
+        if !(200..300).contains(&status) {
+            let e = format!("Failure: {} answered with HTTP {}", self.url, status);
+            // rate limits and server errors tend to pass, a 404 doesn't
+            return Err(if status == 429 || status >= 500 {
+                Failure::Retry(e.into())
+            } else {
+                Failure::Fatal(e.into())
+            });
+        }
+        let content_type = easy
+            .content_type()?
+            .map(|c| c.split(';').next().unwrap_or("").trim().to_lowercase());
+        if let Some(content_type) = content_type {
+            if !self.content_types.is_empty() && !self.content_types.contains(&&*content_type) {
+                return Err(Failure::Fatal(
+                    format!(
+                        "Failure: {} sent {} instead of {}",
+                        self.url,
+                        content_type,
+                        self.content_types.join(" or ")
+                    )
+                    .into(),
+                ));
+            }
+        }
+        file.sync_all()?;
+
+        Ok(Some(Download {
+            source: self.url.clone(),
+            etag: find_header(&headers, "etag"),
+            last_modified: find_header(&headers, "last-modified"),
+        }))
+    }
+}
+
+// Catches the obvious mistakes before a parser sees a file that was brought in by hand:
+// nothing in it, way too big, an error page or a spreadsheet where a csv is expected
+pub fn check_file(source: &Source, path: &str, max_bytes: u64) -> Result<(), DownloadError> {
+    let len = fs::metadata(path)?.len();
+    if len == 0 {
+        return Err("Failure: the file is empty".into());
+    }
+    if len > max_bytes {
+        return Err(format!("Failure: the file is larger than {} bytes", max_bytes).into());
+    }
+    let mut start = Vec::new();
+    File::open(path)?.take(512).read_to_end(&mut start)?;
+    // xlsx files are zip archives
+    let is_zip = start.starts_with(b"PK\x03\x04");
+    let text = String::from_utf8_lossy(&start).trim_start().to_lowercase();
+    if text.starts_with("<!doctype html") || text.starts_with("<html") {
+        return Err("Failure: the file is an html page".into());
+    }
+    if source.content_types == XLSX_CONTENT_TYPES && !is_zip {
+        return Err(format!(
+            "Failure: {} is expected to be an xlsx file",
+            source.file_name
+        )
+        .into());
+    }
+    if source.content_types == CSV_CONTENT_TYPES && is_zip {
+        return Err(format!("Failure: {} is expected to be a csv file", source.file_name).into());
+    }
+    Ok(())
+}
+
+fn find_header(headers: &[String], name: &str) -> Option<String> {
+    headers.iter().find_map(|header| {
+        let (key, value) = header.split_once(':')?;
+        if key.trim().eq_ignore_ascii_case(name) {
+            Some(value.trim().to_string())
+        } else {
+            None
+        }
+    })
+}
+
+// A path starting with path that no other request of this or another process uses, for files
+// that are written while other requests may be writing theirs
+pub fn unique_path(path: &str) -> String {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    format!(
+        "{}.{}-{}",
+        path,
+        process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::path::Path;
+    use std::thread::JoinHandle;
+
+    // Answers one connection per response and hands back the request headers it got
+    fn serve(responses: Vec<String>) -> (String, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/data.csv", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = String::new();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    request.push_str(&line);
+                }
+                requests.push(request);
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+            requests
+        });
+        (url, handle)
+    }
+
+    fn response(status: &str, content_type: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nETag: \"v1\"\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            content_type,
+            body.len(),
+            body
+        )
+    }
+
+    const SOURCE: Source = Source {
+        url: "https://example.com/publisher/data.csv",
+        file_name: "data.csv",
+        user_agent: None,
+        content_types: CSV_CONTENT_TYPES,
+        charset: None,
+    };
+
+    fn http(url: &str) -> HttpFetcher {
+        HttpFetcher {
+            url: url.to_string(),
+            user_agent: None,
+            content_types: CSV_CONTENT_TYPES,
+            config: DownloadConfig {
+                retries: 2,
+                retry_delay_secs: 0,
+                timeout_secs: 10,
+                max_bytes: 100,
+            },
+        }
+    }
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("iban_beaver_{}", name));
+        let _ = fs::remove_file(&path);
+        path.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn unique_paths() {
+        let path = temp_path("upload");
+        assert_ne!(unique_path(&path), unique_path(&path));
+        assert!(unique_path(&path).starts_with(&path));
+    }
+
+    #[test]
+    fn download_and_not_modified() {
+        let path = temp_path("not_modified.csv");
+        let (url, server) = serve(vec![
+            response("200 OK", "text/csv; charset=utf-8", "a;b\n1;2\n"),
+            response("304 Not Modified", "text/csv", ""),
+        ]);
+        let download = http(&url).fetch(&path, None).unwrap().unwrap();
+        assert_eq!(download.etag.as_deref(), Some("\"v1\""));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a;b\n1;2\n");
+
+        let unchanged = http(&url).fetch(&path, Some(&download)).unwrap();
+        assert!(unchanged.is_none());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a;b\n1;2\n");
+        let requests = server.join().unwrap();
+        assert!(requests[1].contains("If-None-Match: \"v1\""));
+    }
+
+    #[test]
+    fn retry_server_errors() {
+        let path = temp_path("retry.csv");
+        let (url, server) = serve(vec![
+            response("503 Service Unavailable", "text/html", "busy"),
+            response("200 OK", "text/csv", "a;b\n"),
+        ]);
+        assert!(http(&url).fetch(&path, None).is_ok());
+        assert_eq!(server.join().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn keep_file_on_failure() {
+        let path = temp_path("keep.csv");
+        fs::write(&path, "good data").unwrap();
+        let (url, server) = serve(vec![
+            response("404 Not Found", "text/csv", "gone"),
+            response("200 OK", "text/html", "<html>moved</html>"),
+            response("200 OK", "text/csv", &"x".repeat(200)),
+        ]);
+        // none of these are retried, they won't get any better
+        assert!(http(&url).fetch(&path, None).is_err());
+        assert!(http(&url).fetch(&path, None).is_err());
+        assert!(http(&url).fetch(&path, None).is_err());
+        assert_eq!(server.join().unwrap().len(), 3);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "good data");
+        // no partial files are left behind
+        let name = Path::new(&path)
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        let dir = Path::new(&path).parent().unwrap();
+        assert!(!fs::read_dir(dir).unwrap().any(|entry| entry
+            .unwrap()
+            .file_name()
+            .to_string_lossy()
+            .starts_with(&format!("{}.part", name))));
+    }
+
+    #[test]
+    fn check_uploaded_file() {
+        let path = temp_path("check.csv");
+        fs::write(&path, "").unwrap();
+        assert!(check_file(&SOURCE, &path, 100).is_err());
+        fs::write(&path, "<!DOCTYPE html><html>Login</html>").unwrap();
+        assert!(check_file(&SOURCE, &path, 100).is_err());
+        fs::write(&path, b"PK\x03\x04 spreadsheet").unwrap();
+        assert!(check_file(&SOURCE, &path, 100).is_err());
+        fs::write(&path, "a;b\n1;2\n").unwrap();
+        assert!(check_file(&SOURCE, &path, 100).is_ok());
+        assert!(check_file(&SOURCE, &path, 4).is_err());
+    }
+
+    #[test]
+    fn sources_resolution() {
+        let mut sources = SourcesConfig::default();
+        assert_eq!(location(&sources, "DE", &SOURCE), SOURCE.url);
+        sources.mirror = Some("http://mirror.internal/iban/".to_string());
+        assert_eq!(
+            location(&sources, "DE", &SOURCE),
+            "http://mirror.internal/iban/data.csv"
+        );
+        sources
+            .countries
+            .insert("de".to_string(), "/srv/data/de.csv".to_string());
+        assert_eq!(location(&sources, "DE", &SOURCE), "/srv/data/de.csv");
+        assert_eq!(
+            location(&sources, "AT", &SOURCE),
+            "http://mirror.internal/iban/data.csv"
+        );
+    }
+
+    #[test]
+    fn fetch_local_file() {
+        let original = temp_path("local_original.csv");
+        fs::write(&original, "a;b\n1;2\n").unwrap();
+        let mut sources = SourcesConfig::default();
+        sources
+            .countries
+            .insert("DE".to_string(), format!("file://{}", original));
+        let fetcher = fetcher_from(&sources, DownloadConfig::default(), "DE", &SOURCE);
+
+        let path = temp_path("local_copy.csv");
+        let download = fetcher.fetch(&path, None).unwrap().unwrap();
+        assert_eq!(download.source, original);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a;b\n1;2\n");
+        assert!(fetcher.fetch(&path, Some(&download)).unwrap().is_none());
+        let rollback = Download {
+            source: String::from("rollback to dataset 1"),
+            ..download.clone()
+        };
+        assert!(fetcher.fetch(&path, Some(&rollback)).unwrap().is_none());
+
+        fs::remove_file(&original).unwrap();
+        assert!(fetcher.fetch(&path, None).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a;b\n1;2\n");
+    }
+}
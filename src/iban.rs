@@ -1,18 +1,157 @@
 use crate::country::get_country;
 use crate::country::BankData;
-use crate::db::{establish_connection, is_blacklisted};
+use crate::db::establish_connection;
+use diesel::sqlite::SqliteConnection;
 use iban::*;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 //use core::convert::TryFrom;
+// What people paste into a form: "iban: de27 1007-7777.0209 2997 00" with any kind of space.
+// Drops the label, whitespace, zero width characters, dashes, dots and slashes and goes upper case.
+pub fn normalize(input: &str) -> String {
+    let input = input.trim();
+    let input = match input.get(..4) {
+        // "IBAN: ", "IBAN : " and "IBAN " alike
+        Some(label) if label.eq_ignore_ascii_case("iban") => {
+            input[4..].trim_start().trim_start_matches(':')
+        }
+        _ => input,
+    };
+    input
+        .chars()
+        .filter(|c| {
+            !(c.is_whitespace()
+                || matches!(
+                    c,
+                    '\u{200B}'..='\u{200D}'
+                        | '\u{2060}'
+                        | '\u{FEFF}'
+                        | '-'
+                        | '\u{2010}'..='\u{2015}'
+                        | '\u{2212}'
+                        | '.'
+                        | '/'
+                ))
+        })
+        .flat_map(char::to_uppercase)
+        .collect()
+}
 pub fn parse(iban_str: &str) -> Result<Iban, String> {
     iban_str.parse::<Iban>().map_err(|e| e.to_string())
 }
+// Whether parse fails on the check digits, a wrong length fails on them too
+pub fn checksum_error(iban_str: &str) -> bool {
+    matches!(
+        iban_str.parse::<Iban>(),
+        Err(ParseIbanError::InvalidBaseIban {
+            source: ParseBaseIbanError::InvalidChecksum
+        })
+    )
+}
+
+// The national check digits of countries that have them, see Country::national_check
+pub fn national_check(iban: &Iban) -> bool {
+    get_country(iban.country_code()).map_or(true, |country| country.national_check(iban.bban()))
+}
+
+// Where a part is in the electronic format, to highlight it in the IBAN
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+// The parts of a valid IBAN. The account number and national check digits are only known for
+// the countries we have a bank table for, see Country::bban_layout.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+pub struct IbanStructure {
+    pub country_code: String,
+    pub check_digits: String,
+    pub bban: String,
+    pub bank_identifier: Option<String>,
+    pub bank_identifier_span: Option<Span>,
+    pub branch_identifier: Option<String>,
+    pub account_number: Option<String>,
+    pub national_check_digits: Option<String>,
+    // DE27 1007 7777 0209 2997 00
+    pub print: String,
+}
+
+pub fn structure(iban: &Iban) -> IbanStructure {
+    let bban = iban.bban();
+    let layout = get_country(iban.country_code())
+        .ok()
+        .map(|country| country.bban_layout());
+    let part = |range: Option<std::ops::Range<usize>>| range.and_then(|range| bban.get(range));
+    IbanStructure {
+        country_code: iban.country_code().to_string(),
+        check_digits: iban.check_digits_str().to_string(),
+        bban: bban.to_string(),
+        bank_identifier: iban.bank_identifier().map(String::from),
+        // the BBAN starts after the country code and check digits
+        bank_identifier_span: layout.as_ref().map(|layout| Span {
+            start: 4 + layout.bank_code.start,
+            end: 4 + layout.bank_code.end,
+        }),
+        branch_identifier: iban.branch_identifier().map(String::from),
+        account_number: part(layout.as_ref().map(|layout| layout.account_number.clone()))
+            .map(String::from),
+        national_check_digits: part(layout.and_then(|layout| layout.check_digits))
+            .map(String::from),
+        print: iban.to_string(),
+    }
+}
+
+// IBANs one typo away from iban_str that are valid and have a bank we know: one character
+// replaced by another digit or letter, or two neighbours swapped. Electronic format, sorted.
+pub fn suggest(connection: &SqliteConnection, iban_str: &str) -> Vec<String> {
+    let chars = iban_str
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map(|c| c.to_ascii_uppercase())
+        .collect::<Vec<_>>();
+    let country = match get_country(&chars.iter().take(2).collect::<String>()) {
+        Ok(country) => country,
+        Err(_) => return Vec::new(),
+    };
+    let mut candidates = Vec::new();
+    // a typo in the country code makes it another country, those are left alone
+    for i in 2..chars.len() {
+        let replacements = if chars[i].is_ascii_digit() {
+            '0'..='9'
+        } else {
+            'A'..='Z'
+        };
+        for c in replacements.filter(|c| *c != chars[i]) {
+            let mut candidate = chars.clone();
+            candidate[i] = c;
+            candidates.push(candidate);
+        }
+        if i + 1 < chars.len() && chars[i] != chars[i + 1] {
+            let mut candidate = chars.clone();
+            candidate.swap(i, i + 1);
+            candidates.push(candidate);
+        }
+    }
+    let mut suggestions = candidates
+        .into_iter()
+        .filter_map(|candidate| parse(&candidate.into_iter().collect::<String>()).ok())
+        .filter(national_check)
+        .filter(|iban| {
+            iban.bank_identifier()
+                .is_some_and(|code| country.get_bank_data(connection, code).is_ok())
+        })
+        .map(|iban| iban.electronic_str().to_string())
+        .collect::<Vec<_>>();
+    suggestions.sort();
+    suggestions.dedup();
+    suggestions
+}
 pub fn verify(iban: Iban) -> Result<BankData, String> {
     let country = get_country(iban.country_code()).map_err(|e| e.to_string())?;
     let connection = &establish_connection();
-    is_blacklisted(connection, &iban.to_string())?;
     if let Some(bank_code) = iban.bank_identifier() {
-        let bank_data = country.get_bank_data(connection, &bank_code)?;
-        Ok(bank_data)
+        country.get_bank_data(connection, bank_code)
     } else {
         Err(String::from("No bank code found in IBAN"))
     }
@@ -41,6 +180,89 @@ mod tests {
         assert!(de_err.is_err());
     }
 
+    #[test]
+    fn normalize_input() {
+        let electronic = "DE27100777770209299700";
+        for input in [
+            electronic,
+            "DE27 1007 7777 0209 2997 00",
+            "iban: de27 1007 7777 0209 2997 00",
+            "IBAN DE27-1007-7777-0209-2997-00",
+            "IBAN:DE27.1007.7777.0209.2997.00",
+            "IBAN : DE27 1007 7777 0209 2997 00",
+            " de27\u{a0}1007\u{202f}7777\t0209\u{200b}2997\u{2013}00\n",
+        ] {
+            assert_eq!(normalize(input), electronic);
+        }
+        assert_eq!(normalize("NL91 ABNA 0417 1643 00"), "NL91ABNA0417164300");
+    }
+
+    #[test]
+    fn national_check_digits() {
+        assert!(national_check(&parse("BE68539007547034").unwrap()));
+        // passes mod 97 with other check digits, but not the Belgian check
+        assert!(!national_check(&parse("BE19539007547043").unwrap()));
+        assert!(national_check(&parse("DE27100777770209299700").unwrap()));
+    }
+
+    #[test]
+    fn iban_structure() {
+        let de = structure(&parse("DE27100777770209299700").unwrap());
+        assert_eq!(
+            (de.country_code.as_str(), de.check_digits.as_str()),
+            ("DE", "27")
+        );
+        assert_eq!(de.bban, "100777770209299700");
+        assert_eq!(de.bank_identifier.as_deref(), Some("10077777"));
+        assert_eq!(de.bank_identifier_span, Some(Span { start: 4, end: 12 }));
+        assert_eq!(de.account_number.as_deref(), Some("0209299700"));
+        assert_eq!(de.national_check_digits, None);
+        assert_eq!(de.print, "DE27 1007 7777 0209 2997 00");
+
+        let be = structure(&parse("BE68539007547034").unwrap());
+        assert_eq!(be.bank_identifier.as_deref(), Some("539"));
+        assert_eq!(be.account_number.as_deref(), Some("0075470"));
+        assert_eq!(be.national_check_digits.as_deref(), Some("34"));
+
+        let at = structure(&parse("AT611904300234573201").unwrap());
+        assert_eq!(at.bank_identifier.as_deref(), Some("19043"));
+        assert_eq!(at.account_number.as_deref(), Some("00234573201"));
+
+        let nl = structure(&parse("NL91ABNA0417164300").unwrap());
+        assert_eq!(nl.bank_identifier.as_deref(), Some("ABNA"));
+        assert_eq!(nl.bank_identifier_span, Some(Span { start: 4, end: 8 }));
+        assert_eq!(nl.account_number.as_deref(), Some("0417164300"));
+
+        // no bank table, only what iban_validate knows
+        let gb = structure(&parse("GB82WEST12345698765432").unwrap());
+        assert_eq!(gb.bank_identifier.as_deref(), Some("WEST"));
+        assert_eq!(gb.branch_identifier.as_deref(), Some("123456"));
+        assert!(gb.account_number.is_none() && gb.bank_identifier_span.is_none());
+    }
+
+    #[test]
+    #[serial]
+    fn suggest_typos() {
+        let connection = &establish_connection();
+        // last digit wrong
+        assert!(checksum_error("DE27100777770209299704"));
+        let suggestions = suggest(connection, "DE27100777770209299704");
+        assert!(suggestions.contains(&String::from("DE27100777770209299700")));
+        // two neighbours swapped
+        assert!(checksum_error("DE27100777770209297900"));
+        let suggestions = suggest(connection, "DE27100777770209297900");
+        assert!(suggestions.contains(&String::from("DE27100777770209299700")));
+        // every suggestion is valid and has a bank we know
+        for suggestion in suggest(connection, "BE68539007547043") {
+            let iban = parse(&suggestion).unwrap();
+            assert!(national_check(&iban));
+            assert!(verify(iban).is_ok());
+        }
+        // a digit short, no single typo fixes that
+        assert!(suggest(connection, "DE2710077777020929970").is_empty());
+        assert!(suggest(connection, "XX27100777770209299704").is_empty());
+    }
+
     #[test]
     #[serial]
     fn verify_iban() {
@@ -48,10 +270,14 @@ mod tests {
         let de_ok = verify(iban).unwrap();
         assert!(de_ok.code.eq("10077777"));
         assert!(de_ok.bic.eq(&Some("NORSDE51XXX".to_string())));
+        assert_eq!(de_ok.postal_code, Some("10117".to_string()));
+        assert_eq!(de_ok.country, "DE");
 
         let iban = "BE68539007547034".parse::<Iban>().unwrap();
         let be_ok = verify(iban).unwrap();
         assert!(be_ok.code.eq("539"));
         assert!(be_ok.bic.eq(&Some("NAP".to_string())));
+        // the BE source has no address
+        assert!(be_ok.postal_code.is_none() && be_ok.city.is_none());
     }
 }
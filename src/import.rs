@@ -0,0 +1,509 @@
+// Rows of a source file that can't be loaded are reported and skipped instead of failing the whole
+// import, as long as there are fewer of them than the [import] section of Rocket.toml allows
+use crate::bank::NewBank;
+use crate::config;
+use crate::country::schema::rejected_row;
+use calamine::{DataType, Range};
+use diesel::{prelude::*, sqlite::SqliteConnection};
+use encoding_rs::Encoding;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use unicode_normalization::UnicodeNormalization;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+pub struct RowError {
+    // row as a spreadsheet program or text editor shows it, starting at 1
+    pub row: usize,
+    // header of the offending column, when we know which one it was
+    pub column: Option<String>,
+    pub reason: String,
+}
+
+// What a country parser got out of a source file
+#[derive(Debug, Default)]
+pub struct Parsed {
+    pub banks: Vec<NewBank>,
+    pub report: ImportReport,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, JsonSchema)]
+pub struct ImportReport {
+    pub rows_loaded: usize,
+    pub rows_rejected: usize,
+    pub errors: Vec<RowError>,
+}
+impl ImportReport {
+    pub fn reject(&mut self, row: usize, column: Option<&str>, reason: impl ToString) {
+        self.rows_rejected += 1;
+        self.errors.push(RowError {
+            row,
+            column: column.map(String::from),
+            reason: reason.to_string(),
+        });
+    }
+
+    // Call before the table is touched, accepted is the number of rows that parsed fine
+    pub fn check_budget(&self, accepted: usize) -> Result<(), String> {
+        self.check_budget_with(&config::load::<ImportConfig>("import")?, accepted)
+    }
+
+    pub fn check_budget_with(&self, config: &ImportConfig, accepted: usize) -> Result<(), String> {
+        let total = accepted + self.rows_rejected;
+        let percent = if total == 0 {
+            0.0
+        } else {
+            self.rows_rejected as f64 * 100.0 / total as f64
+        };
+        if self.rows_rejected <= config.max_rejected_rows && percent <= config.max_rejected_percent
+        {
+            return Ok(());
+        }
+        let first = self
+            .errors
+            .first()
+            .map(|e| {
+                format!(
+                    ", first at row {}{}: {}",
+                    e.row,
+                    e.column
+                        .as_ref()
+                        .map(|c| format!(" column {}", c))
+                        .unwrap_or_default(),
+                    e.reason
+                )
+            })
+            .unwrap_or_default();
+        Err(format!(
+            "Failure: {} of {} rows rejected, more than the error budget allows{}",
+            self.rows_rejected, total, first
+        ))
+    }
+}
+
+// Publishers put titles, dates and notes above the header, it has to show up within this many rows
+const HEADER_SEARCH_ROWS: usize = 50;
+
+// Finds the header row, the first one that has all expected columns, and where each of them is.
+// Names are compared ignoring case and surrounding whitespace, so a renamed or dropped column fails
+// the import instead of filling the wrong fields.
+pub fn find_header<I>(rows: I, expected: &[&str]) -> Result<(usize, Vec<usize>), String>
+where
+    I: IntoIterator<Item = Vec<String>>,
+{
+    let position = |row: &[String], name: &str| {
+        row.iter()
+            .position(|cell| cell.trim().eq_ignore_ascii_case(name.trim()))
+    };
+    // the row with the most matches, to tell what is missing when there's no header
+    let mut closest: Option<(usize, Vec<&str>)> = None;
+    for (i, row) in rows.into_iter().take(HEADER_SEARCH_ROWS).enumerate() {
+        let columns = expected
+            .iter()
+            .map(|name| position(&row, name))
+            .collect::<Vec<_>>();
+        if columns.iter().all(Option::is_some) {
+            return Ok((i, columns.into_iter().flatten().collect()));
+        }
+        let missing = expected
+            .iter()
+            .zip(&columns)
+            .filter(|(_, column)| column.is_none())
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>();
+        if missing.len() < expected.len()
+            && closest
+                .as_ref()
+                .is_none_or(|(_, m)| missing.len() < m.len())
+        {
+            closest = Some((i, missing));
+        }
+    }
+    Err(match closest {
+        Some((row, missing)) => format!(
+            "Failure: the header (row {}) is missing the columns {}, did the publisher change the format?",
+            row + 1,
+            missing.join(", ")
+        ),
+        None => format!(
+            "Failure: no header with the columns {} found",
+            expected.join(", ")
+        ),
+    })
+}
+
+// Text of a csv source. A BOM decides, otherwise valid UTF-8 is taken as is and anything else is
+// decoded with charset. Note that encoding_rs treats ISO-8859-1 as windows-1252, like browsers do.
+pub fn decode(bytes: &[u8], charset: &str) -> Result<String, String> {
+    if let Some((encoding, bom_length)) = Encoding::for_bom(bytes) {
+        let (text, _) = encoding.decode_without_bom_handling(&bytes[bom_length..]);
+        return Ok(text.into_owned());
+    }
+    if let Ok(text) = std::str::from_utf8(bytes) {
+        return Ok(text.to_string());
+    }
+    let encoding = Encoding::for_label(charset.as_bytes())
+        .ok_or_else(|| format!("Failure: unknown charset {}", charset))?;
+    let (text, _, had_errors) = encoding.decode(bytes);
+    if had_errors {
+        return Err(format!(
+            "Failure: the file is not valid {}",
+            encoding.name()
+        ));
+    }
+    Ok(text.into_owned())
+}
+
+// Names are compared and searched, so the same umlaut shouldn't come in two byte sequences
+pub fn nfc(text: &str) -> String {
+    text.trim().nfc().collect()
+}
+
+// An optional field of a source, None when the cell is empty
+pub fn optional(text: &str) -> Option<String> {
+    Some(nfc(text)).filter(|text| !text.is_empty())
+}
+
+// Spreadsheets store postal codes as numbers, which loses their leading zeros
+pub fn postal_code(cell: &DataType, digits: usize) -> Option<String> {
+    match cell {
+        DataType::Int(code) => Some(format!("{:0width$}", code, width = digits)),
+        DataType::Float(code) => Some(format!("{:0width$}", *code as i64, width = digits)),
+        cell => optional(&cell.to_string()),
+    }
+}
+
+// A spreadsheet row as text, for find_header
+pub fn cells(row: &[DataType]) -> Vec<String> {
+    row.iter().map(|cell| cell.to_string()).collect()
+}
+
+// Returns the first of the required columns that is empty
+pub fn missing<'a>(fields: &[(&'a str, &str)]) -> Option<&'a str> {
+    fields
+        .iter()
+        .find(|(_, value)| value.trim().is_empty())
+        .map(|(column, _)| *column)
+}
+
+// The rows below the header of a sheet, with the row number as a spreadsheet program shows it
+// and the cells of the expected columns in the order of expected. Empty rows are left out.
+pub fn xlsx_rows<'a>(
+    range: &'a Range<DataType>,
+    expected: &[&str],
+) -> Result<impl Iterator<Item = (usize, Vec<String>)> + 'a, String> {
+    let (header, columns) = find_header(range.rows().map(cells), expected)?;
+    // rows are 0 based in calamine and the sheet doesn't have to start at the top
+    let first_row = range.start().map_or(0, |(row, _)| row as usize) + 1;
+    Ok(range
+        .rows()
+        .enumerate()
+        .skip(header + 1)
+        .map(move |(i, row)| {
+            let values = columns
+                .iter()
+                .map(|column| row.get(*column).map(|c| c.to_string()).unwrap_or_default())
+                .collect::<Vec<_>>();
+            (first_row + i, values)
+        })
+        .filter(|(_, values)| values.iter().any(|value| !value.trim().is_empty())))
+}
+
+// A sheet with nothing but a bank code, BIC and name per bank, columns names them in that order.
+// Reserved and unassigned codes come without a BIC.
+pub fn bank_sheet(
+    range: &Range<DataType>,
+    country_code: &str,
+    columns: &[&str; 3],
+) -> Result<Parsed, String> {
+    let mut parsed = Parsed::default();
+    let mut seen = HashSet::new();
+    for (row, values) in xlsx_rows(range, columns)? {
+        let (code, bic, name) = (values[0].clone(), values[1].clone(), nfc(&values[2]));
+        if let Some(column) = missing(&[(columns[0], &code), (columns[2], &name)]) {
+            parsed.report.reject(row, Some(column), "value is missing");
+        } else if !seen.insert(code.clone()) {
+            parsed
+                .report
+                .reject(row, Some(columns[0]), "duplicate bank code");
+        } else {
+            parsed.banks.push(NewBank {
+                country_code: country_code.to_string(),
+                bank_code: code,
+                name,
+                bic: optional(&bic),
+                ..Default::default()
+            });
+        }
+    }
+    Ok(parsed)
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct ImportConfig {
+    // an import fails when more rows than this are rejected
+    pub max_rejected_rows: usize,
+    // or when more than this share of the rows is rejected
+    pub max_rejected_percent: f64,
+    // keep rejected rows in the rejected_row table, otherwise they are only reported
+    pub quarantine: bool,
+    // country code -> charset of its csv source, when it isn't what the publisher used to send
+    pub charsets: HashMap<String, String>,
+}
+impl Default for ImportConfig {
+    fn default() -> ImportConfig {
+        ImportConfig {
+            max_rejected_rows: 100,
+            max_rejected_percent: 5.0,
+            quarantine: true,
+            charsets: HashMap::new(),
+        }
+    }
+}
+impl ImportConfig {
+    pub fn charset<'a>(&'a self, country_code: &str, default: &'a str) -> &'a str {
+        self.charsets
+            .iter()
+            .find(|(code, _)| code.eq_ignore_ascii_case(country_code))
+            .map_or(default, |(_, charset)| charset)
+    }
+}
+
+#[derive(Insertable)]
+#[table_name = "rejected_row"]
+struct NewRejectedRow<'a> {
+    dataset_id: i32,
+    row_number: i32,
+    column_name: Option<&'a str>,
+    reason: &'a str,
+}
+
+pub fn record_rejected(
+    connection: &SqliteConnection,
+    dataset_id: i32,
+    errors: &[RowError],
+) -> QueryResult<usize> {
+    let rows = errors
+        .iter()
+        .map(|e| NewRejectedRow {
+            dataset_id,
+            row_number: e.row as i32,
+            column_name: e.column.as_deref(),
+            reason: &e.reason,
+        })
+        .collect::<Vec<_>>();
+    diesel::insert_into(rejected_row::table)
+        .values(&rows)
+        .execute(connection)
+}
+
+// The quarantined rows of a dataset, in file order
+pub fn get_rejected(connection: &SqliteConnection, dataset_id: i32) -> QueryResult<Vec<RowError>> {
+    let rows = rejected_row::table
+        .filter(rejected_row::dataset_id.eq(dataset_id))
+        .order(rejected_row::row_number.asc())
+        .select((
+            rejected_row::row_number,
+            rejected_row::column_name,
+            rejected_row::reason,
+        ))
+        .load::<(i32, Option<String>, String)>(connection)?;
+    Ok(rows
+        .into_iter()
+        .map(|(row, column, reason)| RowError {
+            row: row as usize,
+            column,
+            reason,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_budget() {
+        let config = ImportConfig {
+            max_rejected_rows: 2,
+            max_rejected_percent: 10.0,
+            quarantine: false,
+            charsets: HashMap::new(),
+        };
+        let mut report = ImportReport::default();
+        assert!(report.check_budget_with(&config, 0).is_ok());
+        report.reject(3, Some("PLZ"), "invalid digit found in string");
+        assert!(report.check_budget_with(&config, 99).is_ok());
+        // 1 of 5 is more than 10 percent
+        let e = report.check_budget_with(&config, 4).unwrap_err();
+        assert!(e.contains("1 of 5 rows"));
+        assert!(e.contains("row 3 column PLZ"));
+        report.reject(4, None, "row is too short");
+        report.reject(5, None, "row is too short");
+        assert!(report.check_budget_with(&config, 1000).is_err());
+        assert_eq!(report.rows_rejected, 3);
+    }
+
+    fn rows(rows: &[&[&str]]) -> Vec<Vec<String>> {
+        rows.iter()
+            .map(|row| row.iter().map(|cell| cell.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn header_detection() {
+        let sheet = rows(&[
+            &["BIC-lijst NL"],
+            &["Versie oktober 2026", ""],
+            &[],
+            &["Naam betaaldienstverlener ", "bic", "Identifier"],
+            &["ABN AMRO BANK N.V.", "ABNANL2A", "ABNA"],
+        ]);
+        let expected = ["BIC", "Identifier", "Naam betaaldienstverlener"];
+        assert_eq!(find_header(sheet, &expected), Ok((3, vec![1, 2, 0])));
+
+        let renamed = rows(&[&["BIC", "Bank code", "Naam betaaldienstverlener"]]);
+        let e = find_header(renamed, &expected).unwrap_err();
+        assert!(e.contains("row 1"));
+        assert!(e.contains("missing the columns Identifier"));
+        assert!(find_header(rows(&[&["foo"]]), &expected).is_err());
+    }
+
+    #[test]
+    fn bank_sheets() {
+        // starts on the third row of the sheet, like the cell range of an xlsx can
+        let range = sheet(
+            2,
+            &[
+                &["BIC-lijst NL", "", ""],
+                &["Naam betaaldienstverlener", "BIC", "Identifier"],
+                &["ABN AMRO BANK N.V.", "ABNANL2A", "ABNA"],
+                &["", "", ""],
+                &["Gereserveerd", "", "INGX"],
+                &["", "ABNANL2A", "ABNB"],
+                &["ABN AMRO BANK N.V.", "ABNANL2A", "ABNA"],
+            ],
+        );
+        let columns = ["Identifier", "BIC", "Naam betaaldienstverlener"];
+        let found = xlsx_rows(&range, &columns).unwrap().collect::<Vec<_>>();
+        assert_eq!(found.len(), 4);
+        assert_eq!(
+            found[0],
+            (
+                5,
+                rows(&[&["ABNA", "ABNANL2A", "ABN AMRO BANK N.V."]]).remove(0)
+            )
+        );
+        let parsed = bank_sheet(&range, "NL", &columns).unwrap();
+        assert_eq!(parsed.banks.len(), 2);
+        assert_eq!(parsed.banks[0].bic.as_deref(), Some("ABNANL2A"));
+        // a reserved code without a BIC still loads
+        assert_eq!(parsed.banks[1].bank_code, "INGX");
+        assert_eq!(parsed.banks[1].bic, None);
+        let rejected = parsed
+            .report
+            .errors
+            .iter()
+            .map(|e| {
+                (
+                    e.row,
+                    e.column.as_deref().unwrap_or_default(),
+                    e.reason.as_str(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rejected,
+            [
+                (8, "Naam betaaldienstverlener", "value is missing"),
+                (9, "Identifier", "duplicate bank code")
+            ]
+        );
+
+        let be = sheet(
+            0,
+            &[
+                &["T_Identification_Number", "T_Institutions_Dutch", "Biccode"],
+                &["539", "Nagelmackers", "NAPBBE22"],
+                &["600", "Vrij", ""],
+            ],
+        );
+        let columns = ["T_Identification_Number", "Biccode", "T_Institutions_Dutch"];
+        let parsed = bank_sheet(&be, "BE", &columns).unwrap();
+        assert!(parsed.report.errors.is_empty());
+        assert_eq!(parsed.banks[1].bank_code, "600");
+        assert_eq!(parsed.banks[1].bic, None);
+    }
+
+    fn sheet(first_row: u32, rows: &[&[&str]]) -> Range<DataType> {
+        let mut range = Range::new((first_row, 0), (first_row + rows.len() as u32 - 1, 2));
+        for (row, values) in rows.iter().enumerate() {
+            for (column, value) in values.iter().enumerate() {
+                range.set_value(
+                    (first_row + row as u32, column as u32),
+                    DataType::String(value.to_string()),
+                );
+            }
+        }
+        range
+    }
+
+    #[test]
+    fn decode_charsets() {
+        // "€ „Bank“ Österreich" in windows-1252
+        let cp1252 = b"\x80 \x84Bank\x93 \xd6sterreich";
+        assert_eq!(
+            decode(cp1252, "windows-1252").unwrap(),
+            "€ „Bank“ Österreich"
+        );
+        assert_eq!(
+            decode(b"\xd6sterreich", "iso-8859-1").unwrap(),
+            "Österreich"
+        );
+        assert_eq!(
+            decode("\u{feff}Bankleitzahl;Straße".as_bytes(), "windows-1252").unwrap(),
+            "Bankleitzahl;Straße"
+        );
+        assert_eq!(
+            decode("Straße".as_bytes(), "windows-1252").unwrap(),
+            "Straße"
+        );
+        assert!(decode(b"\xd6", "no-such-charset").is_err());
+    }
+
+    #[test]
+    fn normalize_names() {
+        // o followed by a combining diaeresis
+        assert_eq!(
+            nfc(" O\u{308}sterreichische Nationalbank "),
+            "Österreichische Nationalbank"
+        );
+        assert_eq!(nfc("Österreich"), "Österreich");
+        assert_eq!(optional(" Wien "), Some(String::from("Wien")));
+        assert_eq!(optional("  "), None);
+    }
+
+    #[test]
+    fn postal_codes() {
+        assert_eq!(
+            postal_code(&DataType::Float(1067.0), 5),
+            Some(String::from("01067"))
+        );
+        assert_eq!(
+            postal_code(&DataType::Int(10117), 5),
+            Some(String::from("10117"))
+        );
+        assert_eq!(
+            postal_code(&DataType::String(String::from("D-01067")), 5),
+            Some(String::from("D-01067"))
+        );
+        assert_eq!(postal_code(&DataType::Empty, 5), None);
+    }
+
+    #[test]
+    fn missing_fields() {
+        assert_eq!(missing(&[("BIC", "ABCDBEBB"), ("Name", "Bank")]), None);
+        assert_eq!(missing(&[("BIC", "ABCDBEBB"), ("Name", " ")]), Some("Name"));
+    }
+}
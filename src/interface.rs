@@ -1,15 +1,52 @@
-use crate::country::get_country;
+use crate::allowlist::{allowed, allowlist, allowlist_status, mode, AllowlistStatus};
+use crate::bank::{get_banks_by_bic, Bank};
+use crate::blacklist::{
+    blacklist, blacklist_status, export_blacklist, get_history, import_blacklist, read_records,
+    write_records, BlacklistChange, BlacklistEntry, BlacklistImport, BlacklistStatus, Format,
+    ImportMode, Scope, Subject,
+};
+use crate::changes::{get_changes, DatasetChange};
 use crate::country::BankData;
-use crate::db::{blacklist, establish_connection};
-use crate::iban;
+use crate::country::{get_country, SUPPORTED_COUNTRIES};
+use crate::dataset::{get_dataset, get_datasets, load_table, rollback_table, Dataset, Load};
+use crate::db::{
+    establish_connection, get_refreshes, load_file, record_refresh, refresh_table, upload_table,
+};
+use crate::download::{unique_path, Download};
+use crate::iban::{self, IbanStructure};
+use crate::import::{get_rejected, ImportReport, RowError};
+use crate::privacy::{rotate_key, PrivacyConfig};
+use crate::scheduler::SchedulerConfig;
+use crate::tenant::Tenant;
+use ::iban::IbanLike;
+use chrono::{Duration as ChronoDuration, NaiveDate, NaiveDateTime, Utc};
 use rocket::serde::{Deserialize, Serialize};
 use schemars::JsonSchema;
+use std::fs;
+use std::thread;
+use std::time::{Duration, Instant};
 
 #[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct IbanResponse {
+    // as it was sent
     iban: String,
     valid: bool,
+    // whether iban had to be cleaned up before it was parsed, see iban::normalize
+    normalized: bool,
+    // DE27100777770209299700, only for valid IBANs
+    electronic: Option<String>,
+    // DE27 1007 7777 0209 2997 00
+    print: Option<String>,
+    // the parts of a valid IBAN, bank code included
+    structure: Option<IbanStructure>,
     bank_data: Option<BankData>,
+    // a blacklisted IBAN still gets its bank data
+    blacklist: BlacklistStatus,
+    allowlist: AllowlistStatus,
+    // what to go by, the lists only tell which of their rules matched, see allowlist::allowed
+    allowed: bool,
+    // valid IBANs one typo away, when the check digits didn't match, see iban::suggest
+    suggestions: Vec<String>,
     message: String,
 }
 impl IbanResponse {
@@ -17,7 +54,15 @@ impl IbanResponse {
         IbanResponse {
             iban: iban.to_string(),
             valid: false,
+            normalized: false,
+            electronic: None,
+            print: None,
+            structure: None,
             bank_data: None,
+            blacklist: BlacklistStatus::default(),
+            allowlist: AllowlistStatus::default(),
+            allowed: false,
+            suggestions: Vec::new(),
             message: "".to_string(),
         }
     }
@@ -28,82 +73,1127 @@ pub struct DbResponse {
     pub message: String,
 }
 
-pub fn verify_request(iban_str: &str) -> IbanResponse {
+#[derive(Serialize, Deserialize, Debug, PartialEq, JsonSchema)]
+pub enum UpdateStatus {
+    Updated,
+    // the source hasn't changed since the last load
+    Unchanged,
+    DownloadFailed,
+    FillFailed,
+}
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct UpdateReport {
+    pub country: String,
+    pub status: UpdateStatus,
+    pub rows_loaded: usize,
+    pub rows_rejected: usize,
+    pub errors: Vec<RowError>,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+}
+#[derive(Serialize, Deserialize, Debug, Default, JsonSchema)]
+pub struct UpdateAllResponse {
+    pub success: bool,
+    pub reports: Vec<UpdateReport>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct RefreshStatus {
+    pub country: String,
+    pub last_attempt: Option<NaiveDateTime>,
+    pub last_success: Option<NaiveDateTime>,
+    pub last_error: Option<String>,
+    pub failures: i32,
+    // no successful refresh within scheduler.max_age_hours
+    pub stale: bool,
+}
+#[derive(Serialize, Deserialize, Debug, Default, JsonSchema)]
+pub struct RefreshStatusResponse {
+    pub success: bool,
+    pub message: String,
+    pub countries: Vec<RefreshStatus>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, JsonSchema)]
+pub struct DatasetResponse {
+    pub success: bool,
+    pub message: String,
+    pub dataset: Option<Dataset>,
+    // the rows that were skipped, only when the request loaded a table
+    pub report: Option<ImportReport>,
+}
+impl DatasetResponse {
+    fn loaded(load: Load) -> DatasetResponse {
+        let mut message = format!(
+            "Success: table has been (re)filled with {} rows",
+            load.report.rows_loaded
+        );
+        if load.report.rows_rejected > 0 {
+            message += &format!(", {} rows rejected", load.report.rows_rejected);
+        }
+        DatasetResponse {
+            success: true,
+            message,
+            dataset: Some(load.dataset),
+            report: Some(load.report),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, JsonSchema)]
+pub struct RejectedResponse {
+    pub success: bool,
+    pub message: String,
+    pub rows: Vec<RowError>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, JsonSchema)]
+pub struct DatasetsResponse {
+    pub success: bool,
+    pub message: String,
+    pub datasets: Vec<Dataset>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, JsonSchema)]
+pub struct BanksResponse {
+    pub success: bool,
+    pub message: String,
+    pub banks: Vec<Bank>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, JsonSchema)]
+pub struct BlacklistHistoryResponse {
+    pub success: bool,
+    pub message: String,
+    pub history: Vec<BlacklistChange>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, JsonSchema)]
+pub struct BlacklistImportResponse {
+    pub success: bool,
+    pub message: String,
+    pub report: Option<BlacklistImport>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, JsonSchema)]
+pub struct ChangesResponse {
+    pub success: bool,
+    pub message: String,
+    pub changes: Vec<DatasetChange>,
+}
+
+// Only the rules of tenant apply, IBANs of countries it hasn't enabled aren't allowed
+pub fn verify_request(tenant: &Tenant, privacy: &PrivacyConfig, iban_str: &str) -> IbanResponse {
     let mut iban_response = IbanResponse::new(iban_str);
+    let normalized = iban::normalize(iban_str);
+    iban_response.normalized = normalized != iban_str;
 
-    let iban = match iban::parse(iban_str) {
+    let iban = match iban::parse(&normalized) {
         Ok(iban) => {
             iban_response.valid = true;
             iban
         }
         Err(e) => {
+            if iban::checksum_error(&normalized)
+                && tenant.country_enabled(normalized.get(..2).unwrap_or_default())
+            {
+                iban_response.suggestions = iban::suggest(&establish_connection(), &normalized);
+            }
             iban_response.message = e;
             return iban_response;
         }
     };
 
+    let electronic = iban.electronic_str().to_string();
+    iban_response.electronic = Some(electronic.clone());
+    iban_response.print = Some(iban.to_string());
+    iban_response.structure = Some(iban::structure(&iban));
+    if !tenant.country_enabled(iban.country_code()) {
+        iban_response.message = format!(
+            "Failure: {} is not enabled for this tenant",
+            iban.country_code()
+        );
+        return iban_response;
+    }
+    let bank_code = iban.bank_identifier().map(String::from);
     match iban::verify(iban) {
         Ok(bd) => {
             iban_response.bank_data = Some(bd);
         }
         Err(e) => iban_response.message = e,
     };
+    // rules on banks and BICs need the bank data, the others apply without it
+    let subject = Subject {
+        iban: &electronic,
+        bank_code: bank_code.as_deref(),
+        bic: iban_response
+            .bank_data
+            .as_ref()
+            .and_then(|bd| bd.bic.as_deref()),
+    };
+    let connection = &establish_connection();
+    let name = tenant.name.as_str();
+    let statuses = blacklist_status(connection, privacy, name, &subject).and_then(|blacklist| {
+        let allowlist = allowlist_status(connection, privacy, name, &subject)?;
+        Ok((
+            blacklist,
+            allowlist,
+            tenant.config.mode.map_or_else(mode, Ok)?,
+        ))
+    });
+    match statuses {
+        Ok((blacklist, allowlist, mode)) => {
+            iban_response.allowed = allowed(mode, blacklist.rule.as_ref(), allowlist.rule.as_ref());
+            iban_response.blacklist = blacklist;
+            iban_response.allowlist = allowlist;
+        }
+        Err(e) => iban_response.message = e,
+    }
     iban_response
 }
 
-pub fn fill_table_request(country_code: &str) -> DbResponse {
+pub fn fill_table_request(country_code: &str) -> DatasetResponse {
     let connection = &establish_connection();
-    let mut db_response = DbResponse::default();
+    let mut response = DatasetResponse::default();
     match get_country(country_code) {
-        Ok(country) => match country.fill_table(connection) {
-            Ok(_) => {
-                db_response.success = true;
-                db_response.message = String::from("Success: table has been (re)filled with data");
-            }
-            Err(e) => db_response.message = format!("{:?}", e),
+        Ok(country) => match load_table(connection, country.as_ref(), &country.data_file(), None) {
+            Ok(load) => response = DatasetResponse::loaded(load),
+            Err(e) => response.message = format!("{:?}", e),
         },
-        Err(e) => db_response.message = e,
+        Err(e) => response.message = e,
     };
-    db_response
+    response
 }
-pub fn update_table_request(iban: &str) -> DbResponse {
+pub fn update_table_request(country_code: &str) -> DatasetResponse {
     let connection = &establish_connection();
-    let mut db_response = DbResponse::default();
-    match get_country(iban) {
-        Ok(country) => match country.update_table(connection) {
-            Ok(_) => {
-                db_response.success = true;
-                db_response.message = String::from("Success: table has been (re)filled with data");
-            }
-            Err(e) => db_response.message = format!("{:?}", e),
+    let mut response = DatasetResponse::default();
+    match refresh_table(connection, country_code) {
+        Ok(Some(load)) => response = DatasetResponse::loaded(load),
+        Ok(None) => {
+            response.success = true;
+            response.message = String::from("Success: source is unchanged, table was kept");
+        }
+        Err(e) => response.message = e,
+    };
+    response
+}
+// Refresh a country from a file instead of downloading it, name is what shows up as its source
+pub fn upload_request(country_code: &str, path: &str, name: &str) -> DatasetResponse {
+    let connection = &establish_connection();
+    match upload_table(connection, country_code, path, name) {
+        Ok(load) => DatasetResponse::loaded(load),
+        Err(e) => DatasetResponse {
+            message: e,
+            ..Default::default()
         },
-        Err(e) => db_response.message = e,
+    }
+}
+pub fn update_all_request() -> UpdateAllResponse {
+    let connection = &establish_connection();
+    // downloads are network bound, so fetch all countries at the same time
+    let downloads = SUPPORTED_COUNTRIES
+        .iter()
+        .map(|&country_code| {
+            let previous = get_dataset(connection, country_code)
+                .ok()
+                .flatten()
+                .map(|dataset| Download::from(&dataset));
+            let handle = thread::spawn(move || {
+                let start = Instant::now();
+                // next to the data file, which it only replaces once it loaded
+                let result = get_country(country_code).and_then(|country| {
+                    let path = unique_path(&country.data_file());
+                    country
+                        .download_data(&path, previous.as_ref())
+                        .map(|download| download.map(|download| (download, path)))
+                        .map_err(|e| e.to_string())
+                });
+                (result, start.elapsed())
+            });
+            (country_code, handle)
+        })
+        .collect::<Vec<_>>();
+
+    // sqlite only allows one writer, so the tables are filled one at a time
+    let reports = downloads
+        .into_iter()
+        .map(|(country_code, handle)| {
+            let (downloaded, download_time) = handle
+                .join()
+                .unwrap_or_else(|_| (Err(String::from("download panicked")), Duration::default()));
+            let mut report = UpdateReport {
+                country: country_code.to_string(),
+                status: UpdateStatus::DownloadFailed,
+                rows_loaded: 0,
+                rows_rejected: 0,
+                errors: Vec::new(),
+                duration_ms: download_time.as_millis() as u64,
+                error: None,
+            };
+            let (download, path) = match downloaded {
+                Ok(Some(downloaded)) => downloaded,
+                Ok(None) => {
+                    let _ = record_refresh(connection, country_code, Ok(()));
+                    report.status = UpdateStatus::Unchanged;
+                    return report;
+                }
+                Err(e) => {
+                    let _ = record_refresh(connection, country_code, Err(e.clone()));
+                    report.error = Some(e);
+                    return report;
+                }
+            };
+            let start = Instant::now();
+            let filled = get_country(country_code)
+                .and_then(|country| load_file(connection, country.as_ref(), &path, download));
+            report.duration_ms += start.elapsed().as_millis() as u64;
+            let _ = record_refresh(
+                connection,
+                country_code,
+                filled.as_ref().map(|_| ()).map_err(String::clone),
+            );
+            match filled {
+                Ok(load) => {
+                    report.status = UpdateStatus::Updated;
+                    report.rows_loaded = load.report.rows_loaded;
+                    report.rows_rejected = load.report.rows_rejected;
+                    report.errors = load.report.errors;
+                }
+                Err(e) => {
+                    report.status = UpdateStatus::FillFailed;
+                    report.error = Some(e);
+                }
+            }
+            report
+        })
+        .collect::<Vec<_>>();
+
+    UpdateAllResponse {
+        success: reports
+            .iter()
+            .all(|r| r.status == UpdateStatus::Updated || r.status == UpdateStatus::Unchanged),
+        reports,
+    }
+}
+pub fn dataset_request(country_code: &str) -> DatasetResponse {
+    let mut response = DatasetResponse::default();
+    if let Err(e) = get_country(country_code) {
+        response.message = e;
+        return response;
+    }
+    match get_dataset(&establish_connection(), country_code) {
+        Ok(Some(dataset)) => {
+            response.success = true;
+            response.dataset = Some(dataset);
+        }
+        Ok(None) => response.message = String::from("Failure: no data has been loaded yet"),
+        Err(e) => response.message = e.to_string(),
+    }
+    response
+}
+pub fn datasets_request(country_code: &str) -> DatasetsResponse {
+    let mut response = DatasetsResponse::default();
+    if let Err(e) = get_country(country_code) {
+        response.message = e;
+        return response;
+    }
+    match get_datasets(&establish_connection(), country_code) {
+        Ok(datasets) => {
+            response.success = true;
+            response.datasets = datasets;
+        }
+        Err(e) => response.message = e.to_string(),
+    }
+    response
+}
+pub fn rollback_request(country_code: &str, dataset_id: i32) -> DatasetResponse {
+    let mut response = DatasetResponse::default();
+    match rollback_table(&establish_connection(), country_code, dataset_id) {
+        Ok(load) => {
+            response = DatasetResponse::loaded(load);
+            response.message = format!(
+                "Success: table has been rolled back to dataset {}",
+                dataset_id
+            );
+        }
+        Err(e) => response.message = format!("{:?}", e),
+    }
+    response
+}
+// The rows of a dataset that were quarantined when it was loaded
+pub fn rejected_request(country_code: &str, dataset_id: i32) -> RejectedResponse {
+    let mut response = RejectedResponse::default();
+    let connection = &establish_connection();
+    let dataset = get_country(country_code).and_then(|country| {
+        get_datasets(connection, country.country_code())
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .find(|dataset| dataset.id == dataset_id)
+            .ok_or_else(|| String::from("Failure: no such dataset for this country"))
+    });
+    match dataset
+        .and_then(|dataset| get_rejected(connection, dataset.id).map_err(|e| e.to_string()))
+    {
+        Ok(rows) => {
+            response.success = true;
+            response.rows = rows;
+        }
+        Err(e) => response.message = e,
+    }
+    response
+}
+// Every bank with this BIC in every supported country, an 8 character BIC also finds its branches
+pub fn banks_request(bic: &str) -> BanksResponse {
+    let mut response = BanksResponse::default();
+    let bic = bic.trim();
+    if !(bic.len() == 8 || bic.len() == 11) || !bic.chars().all(|c| c.is_ascii_alphanumeric()) {
+        response.message = String::from("Failure: a BIC has 8 or 11 letters and digits");
+        return response;
+    }
+    match get_banks_by_bic(&establish_connection(), bic) {
+        Ok(banks) => {
+            response.success = true;
+            response.message = format!("Success: {} banks found", banks.len());
+            response.banks = banks;
+        }
+        Err(e) => response.message = e.to_string(),
+    }
+    response
+}
+// since is a date (2021-06-25) or a date and time (2021-06-25T17:36:53) in UTC
+pub fn changes_request(country_code: &str, since: Option<&str>) -> ChangesResponse {
+    let mut response = ChangesResponse::default();
+    if let Err(e) = get_country(country_code) {
+        response.message = e;
+        return response;
+    }
+    let since = match since.map(|since| parse_time("since", since)).transpose() {
+        Ok(since) => since,
+        Err(e) => {
+            response.message = e;
+            return response;
+        }
+    };
+    match get_changes(&establish_connection(), country_code, since) {
+        Ok(changes) => {
+            response.success = true;
+            response.changes = changes;
+        }
+        Err(e) => response.message = e.to_string(),
+    }
+    response
+}
+fn parse_time(name: &str, time: &str) -> Result<NaiveDateTime, String> {
+    NaiveDateTime::parse_from_str(time, "%Y-%m-%dT%H:%M:%S")
+        .or_else(|_| {
+            NaiveDate::parse_from_str(time, "%Y-%m-%d")
+                .map(|date| date.and_hms_opt(0, 0, 0).unwrap_or_default())
+        })
+        .map_err(|_| {
+            format!(
+                "Failure: {} must look like 2021-06-25 or 2021-06-25T17:36:53",
+                name
+            )
+        })
+}
+pub fn refresh_status_request() -> RefreshStatusResponse {
+    let mut response = RefreshStatusResponse::default();
+    let config = match SchedulerConfig::load() {
+        Ok(config) => config,
+        Err(e) => {
+            response.message = e;
+            return response;
+        }
+    };
+    let refreshes = match get_refreshes(&establish_connection()) {
+        Ok(refreshes) => refreshes,
+        Err(e) => {
+            response.message = e.to_string();
+            return response;
+        }
+    };
+    let oldest_fresh = Utc::now().naive_utc() - ChronoDuration::hours(config.max_age_hours);
+    response.countries = SUPPORTED_COUNTRIES
+        .iter()
+        .map(|&country_code| {
+            let refresh = refreshes.iter().find(|r| r.country_code == country_code);
+            let last_success = refresh.and_then(|r| r.last_success);
+            RefreshStatus {
+                country: country_code.to_string(),
+                last_attempt: refresh.map(|r| r.last_attempt),
+                last_success,
+                last_error: refresh.and_then(|r| r.last_error.clone()),
+                failures: refresh.map_or(0, |r| r.failures),
+                stale: last_success.is_none_or(|t| t < oldest_fresh),
+            }
+        })
+        .collect();
+    response.success = true;
+    response
+}
+// The optional query parameters of an add or remove.
+// expires is a date or date and time in UTC like since of changes_request.
+#[derive(Debug, Default)]
+pub struct RuleParams<'a> {
+    pub reason: Option<&'a str>,
+    // blacklist only
+    pub category: Option<&'a str>,
+    pub by: Option<&'a str>,
+    pub expires: Option<&'a str>,
+    // blacklist only, add an IBAN that isn't valid
+    pub raw: bool,
+}
+impl RuleParams<'_> {
+    fn entry(&self) -> Result<BlacklistEntry, String> {
+        Ok(BlacklistEntry {
+            reason: self.reason.map(String::from),
+            category: self.category.map(str::parse).transpose()?,
+            created_by: self.by.map(String::from),
+            expires_at: self
+                .expires
+                .map(|expires| parse_time("expires", expires))
+                .transpose()?,
+            raw: self.raw,
+        })
+    }
+}
+// scope is iban, iban_prefix, bank, bic or country, see blacklist::Scope
+pub fn blacklist_request(
+    tenant: &Tenant,
+    privacy: &PrivacyConfig,
+    scope: &str,
+    value: &str,
+    op: &str,
+    params: &RuleParams,
+) -> DbResponse {
+    let mut db_response = DbResponse::default();
+    let (scope, entry) = match scope
+        .parse::<Scope>()
+        .and_then(|scope| Ok((scope, params.entry()?)))
+    {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            db_response.message = e;
+            return db_response;
+        }
+    };
+    let connection = &establish_connection();
+    match blacklist(connection, privacy, &tenant.name, scope, value, op, &entry) {
+        Ok(_) => db_response.success = true,
+        Err(e) => db_response.message = format!("{:?}", e),
     };
     db_response
 }
-pub fn blacklist_request(iban: &str, op: &str) -> DbResponse {
+pub fn allowlist_request(
+    tenant: &Tenant,
+    privacy: &PrivacyConfig,
+    scope: &str,
+    value: &str,
+    op: &str,
+    params: &RuleParams,
+) -> DbResponse {
     let mut db_response = DbResponse::default();
-    match blacklist(iban, op) {
+    let (scope, entry) = match scope
+        .parse::<Scope>()
+        .and_then(|scope| Ok((scope, params.entry()?)))
+    {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            db_response.message = e;
+            return db_response;
+        }
+    };
+    let connection = &establish_connection();
+    match allowlist(connection, privacy, &tenant.name, scope, value, op, &entry) {
         Ok(_) => db_response.success = true,
         Err(e) => db_response.message = format!("{:?}", e),
     };
     db_response
 }
+// Load blacklist rules from the csv or json file at path (csv by default), merge keeps the rules
+// that aren't in the file (the default), replace drops them. Nothing changes if a row is invalid.
+pub fn blacklist_import_request(
+    tenant: &Tenant,
+    privacy: &PrivacyConfig,
+    path: &str,
+    format: Option<&str>,
+    mode: Option<&str>,
+    by: Option<&str>,
+) -> BlacklistImportResponse {
+    let mut response = BlacklistImportResponse::default();
+    let import = format
+        .unwrap_or("csv")
+        .parse::<Format>()
+        .and_then(|format| Ok((format, mode.unwrap_or("merge").parse::<ImportMode>()?)))
+        .and_then(|(format, mode)| {
+            let bytes = fs::read(path).map_err(|e| format!("Failure: {}", e))?;
+            let rows = read_records(&bytes, format)?;
+            import_blacklist(
+                &establish_connection(),
+                privacy,
+                &tenant.name,
+                rows,
+                mode,
+                by,
+            )
+        });
+    match import {
+        Ok(import) => {
+            response.success = import.errors.is_empty();
+            response.message = if response.success {
+                format!(
+                    "Success: {} rules imported, {} removed",
+                    import.rows_imported, import.rules_removed
+                )
+            } else {
+                format!(
+                    "Failure: {} invalid rows, nothing was imported",
+                    import.errors.len()
+                )
+            };
+            response.report = Some(import);
+        }
+        Err(e) => response.message = e,
+    }
+    response
+}
+// The whole blacklist as csv or json (the default)
+pub fn blacklist_export_request(tenant: &Tenant, format: Option<&str>) -> Result<String, String> {
+    let format = format.unwrap_or("json").parse::<Format>()?;
+    let records =
+        export_blacklist(&establish_connection(), &tenant.name).map_err(|e| e.to_string())?;
+    write_records(&records, format)
+}
+// Every add and remove of a rule, oldest first
+pub fn blacklist_history_request(
+    tenant: &Tenant,
+    privacy: &PrivacyConfig,
+    scope: &str,
+    value: &str,
+) -> BlacklistHistoryResponse {
+    history_request(tenant, privacy, "blacklist", scope, value)
+}
+pub fn allowlist_history_request(
+    tenant: &Tenant,
+    privacy: &PrivacyConfig,
+    scope: &str,
+    value: &str,
+) -> BlacklistHistoryResponse {
+    history_request(tenant, privacy, "allowlist", scope, value)
+}
+fn history_request(
+    tenant: &Tenant,
+    privacy: &PrivacyConfig,
+    list: &str,
+    scope: &str,
+    value: &str,
+) -> BlacklistHistoryResponse {
+    let mut response = BlacklistHistoryResponse::default();
+    match scope.parse::<Scope>().and_then(|scope| {
+        get_history(
+            &establish_connection(),
+            privacy,
+            &tenant.name,
+            list,
+            scope,
+            value,
+        )
+    }) {
+        Ok(history) => {
+            response.success = true;
+            response.history = history;
+        }
+        Err(e) => response.message = e,
+    }
+    response
+}
+// Hashes the stored IBANs with one more key, only from the command line, see privacy::rotate_key
+pub fn rotate_key_request(key: &str) -> DbResponse {
+    let mut response = DbResponse::default();
+    match PrivacyConfig::load().and_then(|config| rotate_key(&establish_connection(), &config, key))
+    {
+        Ok(rules) => {
+            response.success = true;
+            response.message = format!(
+                "Success: {} rules re-keyed, add the key at the end of keys in [privacy]",
+                rules
+            );
+        }
+        Err(e) => response.message = e,
+    }
+    response
+}
 
 #[cfg(test)]
 mod tests {
     use serial_test::serial;
 
     use super::*;
+    use crate::blacklist::Category;
+    use crate::tenant::TenantConfig;
+
+    // Tests add and remove rules of their own tenant, the rules of real tenants are left alone
+    // the test db has no hashed IBANs
+    const PRIVACY: &PrivacyConfig = &PrivacyConfig { keys: Vec::new() };
+
+    fn test_tenant() -> Tenant {
+        Tenant {
+            name: String::from("iban_beaver tests"),
+            ..Default::default()
+        }
+    }
 
     #[test]
     #[serial]
     fn blacklist() {
-        let add = blacklist_request("HEJHOPP", "ADD");
-        let remove = blacklist_request("HEJHOPP", "REMOVE");
+        // not an IBAN, so it only goes in raw
+        let invalid = blacklist_request(
+            &test_tenant(),
+            PRIVACY,
+            "iban",
+            "HEJHOPP",
+            "ADD",
+            &RuleParams::default(),
+        );
+        let raw = RuleParams {
+            raw: true,
+            ..Default::default()
+        };
+        let add = blacklist_request(&test_tenant(), PRIVACY, "iban", "HEJHOPP", "ADD", &raw);
+        let remove = blacklist_request(
+            &test_tenant(),
+            PRIVACY,
+            "iban",
+            "HEJHOPP",
+            "REMOVE",
+            &RuleParams::default(),
+        );
         println!("WARNING: if you see this message, blacklist test failed and your blacklist may be tainted in your test db");
+        assert!(!invalid.success);
         assert!(add.success);
         assert!(remove.success);
+        let checksum = "DE27100777770209299704";
+        assert!(
+            !blacklist_request(
+                &test_tenant(),
+                PRIVACY,
+                "iban",
+                checksum,
+                "add",
+                &RuleParams::default()
+            )
+            .success
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn blacklist_normalized() {
+        let add = blacklist_request(
+            &test_tenant(),
+            PRIVACY,
+            "iban",
+            "de27 1007 7777 0209 2997 00",
+            "add",
+            &RuleParams::default(),
+        );
+        let listed = verify_request(&test_tenant(), PRIVACY, "DE27100777770209299700").blacklist;
+        let remove = blacklist_request(
+            &test_tenant(),
+            PRIVACY,
+            "iban",
+            "DE27 1007 7777 0209 2997 00",
+            "remove",
+            &RuleParams::default(),
+        );
+        assert!(add.success && remove.success);
+        assert!(listed.listed);
+        assert_eq!(listed.rule.unwrap().value, "DE27100777770209299700");
+        assert!(
+            !verify_request(&test_tenant(), PRIVACY, "DE27100777770209299700")
+                .blacklist
+                .listed
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn verify_blacklisted() {
+        let iban = "DE27100777770209299700";
+        assert!(
+            !verify_request(&test_tenant(), PRIVACY, iban)
+                .blacklist
+                .listed
+        );
+        assert!(
+            !blacklist_request(
+                &test_tenant(),
+                PRIVACY,
+                "iban",
+                iban,
+                "add",
+                &RuleParams {
+                    category: Some("stolen"),
+                    ..Default::default()
+                }
+            )
+            .success
+        );
+        assert!(
+            !blacklist_request(
+                &test_tenant(),
+                PRIVACY,
+                "iban",
+                iban,
+                "add",
+                &RuleParams {
+                    expires: Some("tomorrow"),
+                    ..Default::default()
+                }
+            )
+            .success
+        );
+        let add = blacklist_request(
+            &test_tenant(),
+            PRIVACY,
+            "iban",
+            iban,
+            "add",
+            &RuleParams {
+                reason: Some("chargeback"),
+                category: Some("fraud"),
+                by: Some("tester"),
+                expires: Some("2999-01-01"),
+                raw: false,
+            },
+        );
+        assert!(add.success);
+        let response = verify_request(&test_tenant(), PRIVACY, iban);
+        blacklist_request(
+            &test_tenant(),
+            PRIVACY,
+            "iban",
+            iban,
+            "remove",
+            &RuleParams::default(),
+        );
+        assert!(response.blacklist.listed);
+        assert_eq!(response.blacklist.reason.as_deref(), Some("chargeback"));
+        assert_eq!(response.blacklist.category, Some(Category::Fraud));
+        assert!(response.blacklist.expires_at.is_some());
+        assert_eq!(response.blacklist.listed_by.as_deref(), Some("tester"));
+        assert!(response.blacklist.since.is_some());
+        assert!(response.bank_data.is_some());
+        assert_eq!(
+            verify_request(&test_tenant(), PRIVACY, iban).blacklist,
+            BlacklistStatus::default()
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn verify_blacklisted_bank() {
+        let iban = "DE27100777770209299700";
+        assert!(
+            blacklist_request(
+                &test_tenant(),
+                PRIVACY,
+                "bank",
+                "DE10077777",
+                "add",
+                &RuleParams::default()
+            )
+            .success
+        );
+        assert!(
+            blacklist_request(
+                &test_tenant(),
+                PRIVACY,
+                "bic",
+                "NORSDE51",
+                "add",
+                &RuleParams::default()
+            )
+            .success
+        );
+        let response = verify_request(&test_tenant(), PRIVACY, iban);
+        blacklist_request(
+            &test_tenant(),
+            PRIVACY,
+            "bank",
+            "DE10077777",
+            "remove",
+            &RuleParams::default(),
+        );
+        blacklist_request(
+            &test_tenant(),
+            PRIVACY,
+            "bic",
+            "NORSDE51",
+            "remove",
+            &RuleParams::default(),
+        );
+        let rule = response.blacklist.rule.unwrap();
+        assert_eq!(
+            (rule.scope, rule.value.as_str()),
+            (Scope::Bank, "DE10077777")
+        );
+        assert!(
+            !blacklist_request(
+                &test_tenant(),
+                PRIVACY,
+                "bank",
+                "10077777",
+                "add",
+                &RuleParams::default()
+            )
+            .success
+        );
+        assert!(
+            !blacklist_request(
+                &test_tenant(),
+                PRIVACY,
+                "branch",
+                "DE10077777",
+                "add",
+                &RuleParams::default()
+            )
+            .success
+        );
+        assert!(
+            !verify_request(&test_tenant(), PRIVACY, iban)
+                .blacklist
+                .listed
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn verify_allowlist_exemption() {
+        let iban = "DE27100777770209299700";
+        assert!(verify_request(&test_tenant(), PRIVACY, iban).allowed);
+        let history = allowlist_history_request(&test_tenant(), PRIVACY, "iban", iban)
+            .history
+            .len();
+        blacklist_request(
+            &test_tenant(),
+            PRIVACY,
+            "bank",
+            "DE10077777",
+            "add",
+            &RuleParams::default(),
+        );
+        let blocked = verify_request(&test_tenant(), PRIVACY, iban);
+        let payroll = RuleParams {
+            reason: Some("payroll"),
+            ..Default::default()
+        };
+        allowlist_request(&test_tenant(), PRIVACY, "iban", iban, "add", &payroll);
+        let exempted = verify_request(&test_tenant(), PRIVACY, iban);
+        allowlist_request(
+            &test_tenant(),
+            PRIVACY,
+            "iban",
+            iban,
+            "remove",
+            &RuleParams::default(),
+        );
+        blacklist_request(
+            &test_tenant(),
+            PRIVACY,
+            "bank",
+            "DE10077777",
+            "remove",
+            &RuleParams::default(),
+        );
+        assert!(!blocked.allowed);
+        assert!(exempted.blacklist.listed && exempted.allowlist.listed);
+        assert!(exempted.allowed);
+        assert_eq!(
+            allowlist_history_request(&test_tenant(), PRIVACY, "iban", iban)
+                .history
+                .len(),
+            history + 2
+        );
+        assert!(
+            !allowlist_request(
+                &test_tenant(),
+                PRIVACY,
+                "iban",
+                iban,
+                "add",
+                &RuleParams {
+                    expires: Some("soon"),
+                    ..Default::default()
+                }
+            )
+            .success
+        );
+        // invalid IBANs are never allowed
+        assert!(!verify_request(&test_tenant(), PRIVACY, "DE27100777770209299704").allowed);
+    }
+
+    #[test]
+    #[serial]
+    fn verify_suggests() {
+        let response = verify_request(&Tenant::default(), PRIVACY, "DE27100777770209299704");
+        assert!(!response.valid && !response.allowed);
+        assert!(response
+            .suggestions
+            .contains(&String::from("DE27100777770209299700")));
+        let valid = verify_request(&Tenant::default(), PRIVACY, "DE27100777770209299700");
+        assert!(valid.suggestions.is_empty());
+    }
+
+    #[test]
+    #[serial]
+    fn verify_pasted() {
+        let response = verify_request(
+            &Tenant::default(),
+            PRIVACY,
+            "iban: de27\u{a0}1007-7777.0209 2997 00",
+        );
+        assert!(response.valid && response.normalized);
+        assert_eq!(response.iban, "iban: de27\u{a0}1007-7777.0209 2997 00");
+        assert_eq!(
+            response.electronic.as_deref(),
+            Some("DE27100777770209299700")
+        );
+        assert_eq!(
+            response.print.as_deref(),
+            Some("DE27 1007 7777 0209 2997 00")
+        );
+        assert!(response.bank_data.is_some());
+        let structure = response.structure.unwrap();
+        assert_eq!(structure.bank_identifier.as_deref(), Some("10077777"));
+        assert_eq!(structure.print, "DE27 1007 7777 0209 2997 00");
+        assert!(!verify_request(&Tenant::default(), PRIVACY, "DE27100777770209299700").normalized);
+        // the typo is found in the cleaned up IBAN
+        let typo = verify_request(&Tenant::default(), PRIVACY, "de27 1007 7777 0209 2997 04");
+        assert!(typo.normalized && !typo.valid && typo.electronic.is_none());
+        assert!(typo.structure.is_none());
+        assert!(!typo.suggestions.is_empty());
+    }
+
+    #[test]
+    #[serial]
+    fn verify_per_tenant() {
+        let iban = "DE27100777770209299700";
+        let payroll = test_tenant();
+        let payments = Tenant {
+            name: String::from("iban_beaver tests BE"),
+            config: TenantConfig {
+                countries: vec![String::from("BE")],
+                ..Default::default()
+            },
+        };
+        let default = Tenant::default();
+        let history = |tenant: &Tenant| {
+            blacklist_history_request(tenant, PRIVACY, "iban", iban)
+                .history
+                .len()
+        };
+        let before = history(&default);
+        let add = blacklist_request(
+            &payroll,
+            PRIVACY,
+            "iban",
+            iban,
+            "add",
+            &RuleParams::default(),
+        );
+        let blocked = verify_request(&payroll, PRIVACY, iban);
+        let others = verify_request(&default, PRIVACY, iban);
+        blacklist_request(
+            &payroll,
+            PRIVACY,
+            "iban",
+            iban,
+            "remove",
+            &RuleParams::default(),
+        );
+        assert!(add.success);
+        assert!(blocked.blacklist.listed && !blocked.allowed);
+        assert!(!others.blacklist.listed && others.allowed);
+        assert_eq!(history(&default), before);
+        assert!(history(&payroll) >= 2);
+        // no bank data or rules for a country the tenant hasn't enabled
+        let disabled = verify_request(&payments, PRIVACY, iban);
+        assert!(disabled.valid && !disabled.allowed);
+        assert!(disabled.bank_data.is_none());
+        assert!(verify_request(&payments, PRIVACY, "BE68539007547034").allowed);
+    }
+
+    #[test]
+    #[serial]
+    fn blacklist_import_export() {
+        let path = std::env::temp_dir().join("iban_beaver_blacklist.csv");
+        let path = unique_path(&path.to_string_lossy());
+        std::fs::write(&path, "iban,reason\nDE27100777770209299700,chargeback\n").unwrap();
+        assert!(
+            !blacklist_import_request(&test_tenant(), PRIVACY, &path, Some("xml"), None, None)
+                .success
+        );
+        assert!(
+            !blacklist_import_request(&test_tenant(), PRIVACY, &path, None, Some("append"), None)
+                .success
+        );
+        let import =
+            blacklist_import_request(&test_tenant(), PRIVACY, &path, None, None, Some("tester"));
+        let exported = blacklist_export_request(&test_tenant(), Some("csv")).unwrap();
+        let listed = verify_request(&test_tenant(), PRIVACY, "DE27100777770209299700")
+            .blacklist
+            .listed;
+        blacklist_request(
+            &test_tenant(),
+            PRIVACY,
+            "iban",
+            "DE27100777770209299700",
+            "remove",
+            &RuleParams::default(),
+        );
+        assert!(import.success);
+        assert_eq!(import.report.unwrap().rows_imported, 1);
+        assert!(listed);
+        assert!(exported.starts_with("scope,value,reason,category,created_by,created_at"));
+        assert!(exported.contains("iban,DE27100777770209299700,chargeback,,tester,"));
+        assert!(blacklist_export_request(&test_tenant(), None)
+            .unwrap()
+            .starts_with('['));
+        assert!(blacklist_export_request(&test_tenant(), Some("xlsx")).is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    #[serial]
+    fn upload_rejects_bad_file() {
+        let path = std::env::temp_dir().join("iban_beaver_upload.xlsx");
+        let path = unique_path(&path.to_string_lossy());
+        std::fs::write(&path, "<html>Please log in</html>").unwrap();
+        let response = upload_request("DE", &path, "login.xlsx");
+        assert!(!response.success);
+        assert!(response.dataset.is_none());
+        assert!(!upload_request("DEX", &path, "login.xlsx").success);
+        let _ = std::fs::remove_file(&path);
+        // the table is untouched
+        assert!(
+            verify_request(&Tenant::default(), PRIVACY, "DE27100777770209299700")
+                .bank_data
+                .is_some()
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn banks_by_bic() {
+        let response = banks_request("NORSDE51XXX");
+        assert!(response.success);
+        assert_eq!(response.banks.len(), 1);
+        assert_eq!(response.banks[0].country_code, "DE");
+        // without branch code
+        assert_eq!(banks_request("norsde51").banks.len(), 1);
+        assert!(banks_request("NORSDE51XXXX").banks.is_empty());
+        assert!(!banks_request("NORS DE51").success);
     }
 
     // dont run fill or update tests by default, they can be slow
@@ -164,4 +1254,14 @@ mod tests {
     fn fill_at() {
         assert!(fill_table_request("AT").success);
     }
+
+    // ALL
+    #[test]
+    #[ignore]
+    #[serial]
+    fn update_all() {
+        let response = update_all_request();
+        assert_eq!(response.reports.len(), SUPPORTED_COUNTRIES.len());
+        assert!(response.success);
+    }
 }
@@ -1,7 +1,20 @@
+// diesel 1.x derives expand to impls inside a hidden const, which newer rustc warns about
+#![allow(non_local_definitions)]
 #[macro_use]
 extern crate diesel;
 
+pub mod allowlist;
+pub mod bank;
+pub mod blacklist;
+pub mod changes;
+pub mod config;
 pub mod country;
+pub mod dataset;
 pub mod db;
+pub mod download;
 pub mod iban;
+pub mod import;
 pub mod interface;
+pub mod privacy;
+pub mod scheduler;
+pub mod tenant;
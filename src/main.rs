@@ -1,38 +1,434 @@
 #[macro_use]
 extern crate rocket;
+use iban_beaver::download::unique_path;
 use iban_beaver::interface::*;
+use iban_beaver::privacy::{self, PrivacyConfig};
+use iban_beaver::scheduler;
+use iban_beaver::tenant::{self, Tenant};
+use rocket::data::Capped;
+use rocket::fs::TempFile;
+use rocket::http::{ContentType, Status};
 use rocket::serde::json::Json;
+use rocket::{Request, State};
 use rocket_okapi::{openapi, openapi_get_routes, swagger_ui::*};
+use std::env;
+use std::io;
+use std::process;
 
 // Verify and get the BIC for IBAN you enter
 #[openapi]
 #[get("/verify/<iban_str>")]
-fn verify(iban_str: &str) -> Json<IbanResponse> {
-    Json(verify_request(iban_str))
+fn verify(tenant: Tenant, privacy: &State<PrivacyConfig>, iban_str: &str) -> Json<IbanResponse> {
+    Json(verify_request(&tenant, privacy, iban_str))
 }
 
+// ranked after blacklist_history, which would match add_or_remove otherwise
+// the IBAN is validated and stored electronically, raw=true keeps one that doesn't validate
+#[allow(clippy::too_many_arguments)]
 #[openapi]
-#[get("/blacklist/<iban_str>/<add_or_remove>")]
-fn blacklist(iban_str: &str, add_or_remove: &str) -> Json<DbResponse> {
-    Json(blacklist_request(iban_str, add_or_remove))
+#[get(
+    "/blacklist/<iban_str>/<add_or_remove>?<reason>&<category>&<by>&<expires>&<raw>",
+    rank = 2
+)]
+fn blacklist(
+    tenant: Tenant,
+    privacy: &State<PrivacyConfig>,
+    iban_str: &str,
+    add_or_remove: &str,
+    reason: Option<&str>,
+    category: Option<&str>,
+    by: Option<&str>,
+    expires: Option<&str>,
+    raw: Option<bool>,
+) -> Json<DbResponse> {
+    Json(blacklist_request(
+        &tenant,
+        privacy,
+        "iban",
+        iban_str,
+        add_or_remove,
+        &RuleParams {
+            reason,
+            category,
+            by,
+            expires,
+            raw: raw.unwrap_or(false),
+        },
+    ))
+}
+
+#[openapi]
+#[get("/blacklist/<iban_str>/history")]
+fn blacklist_history(
+    tenant: Tenant,
+    privacy: &State<PrivacyConfig>,
+    iban_str: &str,
+) -> Json<BlacklistHistoryResponse> {
+    Json(blacklist_history_request(
+        &tenant, privacy, "iban", iban_str,
+    ))
+}
+
+// Load blacklist rules from a csv or json file in the request body, see blacklist_import_request
+#[openapi]
+#[post("/blacklist/import?<format>&<mode>&<by>", data = "<file>")]
+async fn blacklist_import(
+    tenant: Tenant,
+    privacy: &State<PrivacyConfig>,
+    format: Option<&str>,
+    mode: Option<&str>,
+    by: Option<&str>,
+    mut file: Capped<TempFile<'_>>,
+) -> Json<BlacklistImportResponse> {
+    if !file.is_complete() {
+        return Json(BlacklistImportResponse {
+            message: String::from("Failure: the file is larger than limits.file allows"),
+            ..Default::default()
+        });
+    }
+    // every request has a file of its own, imports of other tenants run at the same time
+    let path = unique_path(
+        &env::temp_dir()
+            .join("iban_beaver-blacklist")
+            .to_string_lossy(),
+    );
+    if let Err(e) = file.copy_to(&path).await {
+        return Json(BlacklistImportResponse {
+            message: format!("Failure: cannot store the upload: {}", e),
+            ..Default::default()
+        });
+    }
+    let response = blacklist_import_request(&tenant, privacy, &path, format, mode, by);
+    let _ = std::fs::remove_file(&path);
+    Json(response)
+}
+
+// The whole blacklist with its metadata as csv or json (the default)
+#[openapi]
+#[get("/blacklist/export?<format>")]
+fn blacklist_export(tenant: Tenant, format: Option<&str>) -> (Status, (ContentType, String)) {
+    match blacklist_export_request(&tenant, format) {
+        Ok(body) if format.is_some_and(|format| format.eq_ignore_ascii_case("csv")) => {
+            (Status::Ok, (ContentType::CSV, body))
+        }
+        Ok(body) => (Status::Ok, (ContentType::JSON, body)),
+        Err(e) => (Status::BadRequest, (ContentType::Plain, e)),
+    }
+}
+
+// Rules for more than one IBAN, scope is country, bic, bank (DE10077777) or iban_prefix
+#[openapi]
+#[get(
+    "/blacklist/<scope>/<value>/<add_or_remove>?<reason>&<category>&<by>&<expires>&<raw>",
+    rank = 2
+)]
+// every query parameter is an argument of its own
+#[allow(clippy::too_many_arguments)]
+fn blacklist_rule(
+    tenant: Tenant,
+    privacy: &State<PrivacyConfig>,
+    scope: &str,
+    value: &str,
+    add_or_remove: &str,
+    reason: Option<&str>,
+    category: Option<&str>,
+    by: Option<&str>,
+    expires: Option<&str>,
+    raw: Option<bool>,
+) -> Json<DbResponse> {
+    Json(blacklist_request(
+        &tenant,
+        privacy,
+        scope,
+        value,
+        add_or_remove,
+        &RuleParams {
+            reason,
+            category,
+            by,
+            expires,
+            raw: raw.unwrap_or(false),
+        },
+    ))
+}
+
+#[openapi]
+#[get("/blacklist/<scope>/<value>/history")]
+fn blacklist_rule_history(
+    tenant: Tenant,
+    privacy: &State<PrivacyConfig>,
+    scope: &str,
+    value: &str,
+) -> Json<BlacklistHistoryResponse> {
+    Json(blacklist_history_request(&tenant, privacy, scope, value))
+}
+
+// ranked after allowlist_history, which would match add_or_remove otherwise
+#[openapi]
+#[get(
+    "/allowlist/<iban_str>/<add_or_remove>?<reason>&<by>&<expires>",
+    rank = 2
+)]
+fn allowlist(
+    tenant: Tenant,
+    privacy: &State<PrivacyConfig>,
+    iban_str: &str,
+    add_or_remove: &str,
+    reason: Option<&str>,
+    by: Option<&str>,
+    expires: Option<&str>,
+) -> Json<DbResponse> {
+    Json(allowlist_request(
+        &tenant,
+        privacy,
+        "iban",
+        iban_str,
+        add_or_remove,
+        &RuleParams {
+            reason,
+            by,
+            expires,
+            ..Default::default()
+        },
+    ))
+}
+
+#[openapi]
+#[get("/allowlist/<iban_str>/history")]
+fn allowlist_history(
+    tenant: Tenant,
+    privacy: &State<PrivacyConfig>,
+    iban_str: &str,
+) -> Json<BlacklistHistoryResponse> {
+    Json(allowlist_history_request(
+        &tenant, privacy, "iban", iban_str,
+    ))
+}
+
+#[openapi]
+#[get(
+    "/allowlist/<scope>/<value>/<add_or_remove>?<reason>&<by>&<expires>",
+    rank = 2
+)]
+// every query parameter is an argument of its own
+#[allow(clippy::too_many_arguments)]
+fn allowlist_rule(
+    tenant: Tenant,
+    privacy: &State<PrivacyConfig>,
+    scope: &str,
+    value: &str,
+    add_or_remove: &str,
+    reason: Option<&str>,
+    by: Option<&str>,
+    expires: Option<&str>,
+) -> Json<DbResponse> {
+    Json(allowlist_request(
+        &tenant,
+        privacy,
+        scope,
+        value,
+        add_or_remove,
+        &RuleParams {
+            reason,
+            by,
+            expires,
+            ..Default::default()
+        },
+    ))
+}
+
+#[openapi]
+#[get("/allowlist/<scope>/<value>/history")]
+fn allowlist_rule_history(
+    tenant: Tenant,
+    privacy: &State<PrivacyConfig>,
+    scope: &str,
+    value: &str,
+) -> Json<BlacklistHistoryResponse> {
+    Json(allowlist_history_request(&tenant, privacy, scope, value))
 }
 
 #[openapi]
 #[get("/update/<country_code>")]
-fn update(country_code: &str) -> Json<DbResponse> {
+fn update(country_code: &str) -> Json<DatasetResponse> {
     Json(update_table_request(country_code))
 }
 
+#[openapi]
+#[get("/update/all")]
+fn update_all() -> Json<UpdateAllResponse> {
+    Json(update_all_request())
+}
+
+#[openapi]
+#[get("/countries/<country_code>/dataset")]
+fn dataset(country_code: &str) -> Json<DatasetResponse> {
+    Json(dataset_request(country_code))
+}
+
+#[openapi]
+#[get("/countries/<country_code>/datasets")]
+fn datasets(country_code: &str) -> Json<DatasetsResponse> {
+    Json(datasets_request(country_code))
+}
+
+#[openapi]
+#[get("/countries/<country_code>/rollback/<dataset_id>")]
+fn rollback(country_code: &str, dataset_id: i32) -> Json<DatasetResponse> {
+    Json(rollback_request(country_code, dataset_id))
+}
+
+#[openapi]
+#[get("/countries/<country_code>/datasets/<dataset_id>/rejected")]
+fn rejected(country_code: &str, dataset_id: i32) -> Json<RejectedResponse> {
+    Json(rejected_request(country_code, dataset_id))
+}
+
+// Banks in all countries by BIC
+#[openapi]
+#[get("/banks?<bic>")]
+fn banks(bic: &str) -> Json<BanksResponse> {
+    Json(banks_request(bic))
+}
+
+#[openapi]
+#[get("/countries/<country_code>/changes?<since>")]
+fn changes(country_code: &str, since: Option<&str>) -> Json<ChangesResponse> {
+    Json(changes_request(country_code, since))
+}
+
+#[openapi]
+#[get("/refresh-status")]
+fn refresh_status() -> Json<RefreshStatusResponse> {
+    Json(refresh_status_request())
+}
+
 #[openapi]
 #[get("/re-fill/<country_code>")]
-fn fill(country_code: &str) -> Json<DbResponse> {
+fn fill(country_code: &str) -> Json<DatasetResponse> {
     Json(fill_table_request(country_code))
 }
 
-#[launch]
-fn rocket() -> _ {
+// Refresh a country from a source file in the request body, for servers without internet access.
+// Bodies are capped by limits.file in Rocket.toml, name is recorded as where the file came from.
+#[openapi]
+#[post("/countries/<country_code>/upload?<name>", data = "<file>")]
+async fn upload(
+    country_code: &str,
+    name: Option<&str>,
+    mut file: Capped<TempFile<'_>>,
+) -> Json<DatasetResponse> {
+    if !file.is_complete() {
+        return Json(DatasetResponse {
+            message: String::from("Failure: the file is larger than limits.file allows"),
+            ..Default::default()
+        });
+    }
+    let path = unique_path(
+        &env::temp_dir()
+            .join(format!(
+                "iban_beaver-upload-{}",
+                country_code.to_lowercase()
+            ))
+            .to_string_lossy(),
+    );
+    if let Err(e) = file.copy_to(&path).await {
+        return Json(DatasetResponse {
+            message: format!("Failure: cannot store the upload: {}", e),
+            ..Default::default()
+        });
+    }
+    let response = upload_request(country_code, &path, name.unwrap_or("the request body"));
+    let _ = std::fs::remove_file(&path);
+    Json(response)
+}
+
+// An unknown api key or tenant, in json like every other answer
+#[catch(401)]
+fn unauthorized(request: &Request) -> Json<DbResponse> {
+    Json(DbResponse {
+        success: false,
+        message: tenant::rejection(request)
+            .unwrap_or_else(|| String::from("Failure: unauthorized")),
+    })
+}
+
+// iban_beaver upload <country code> <file> loads a file without starting the server
+fn cli(args: &[String]) -> Option<i32> {
+    match args {
+        [command, country_code, path] if command == "upload" => {
+            let response = upload_request(country_code, path, path);
+            println!("{}", response.message);
+            Some(if response.success { 0 } else { 1 })
+        }
+        [command, ..] if command == "upload" => {
+            eprintln!("usage: iban_beaver upload <country code> <file>");
+            Some(2)
+        }
+        // the key is read from stdin so it doesn't end up in the shell history
+        [command] if command == "rotate-key" => {
+            let mut key = String::new();
+            if let Err(e) = io::stdin().read_line(&mut key) {
+                eprintln!("{}", e);
+                return Some(1);
+            }
+            let response = rotate_key_request(key.trim_end_matches(['\r', '\n']));
+            println!("{}", response.message);
+            Some(if response.success { 0 } else { 1 })
+        }
+        [command, ..] if command == "rotate-key" => {
+            eprintln!("usage: echo <new key> | iban_beaver rotate-key");
+            Some(2)
+        }
+        _ => None,
+    }
+}
+
+#[rocket::main]
+async fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    if let Some(code) = cli(&args) {
+        process::exit(code);
+    }
+    if let Err(e) = rocket().launch().await {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
+
+fn rocket() -> rocket::Rocket<rocket::Build> {
     rocket::build()
-        .mount("/", openapi_get_routes![verify, blacklist, update, fill])
+        .attach(privacy::fairing())
+        .attach(tenant::fairing())
+        .attach(scheduler::fairing())
+        .register("/", catchers![unauthorized])
+        .mount(
+            "/",
+            openapi_get_routes![
+                verify,
+                blacklist,
+                blacklist_history,
+                blacklist_rule,
+                blacklist_rule_history,
+                blacklist_import,
+                blacklist_export,
+                allowlist,
+                allowlist_history,
+                allowlist_rule,
+                allowlist_rule_history,
+                update,
+                update_all,
+                refresh_status,
+                dataset,
+                datasets,
+                rollback,
+                rejected,
+                changes,
+                banks,
+                upload,
+                fill
+            ],
+        )
         .mount(
             "/swagger-ui/",
             make_swagger_ui(&SwaggerUIConfig {
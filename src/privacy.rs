@@ -0,0 +1,268 @@
+// Blacklisted and allowlisted IBANs can be stored as a keyed hash (HMAC-SHA256) instead of in plain
+// text, so a copy of the database doesn't give them away. Only rules on single IBANs are hashed,
+// prefixes, banks, BICs and countries stay readable.
+use crate::blacklist::Scope;
+use crate::config;
+use crate::country::schema::{allowlist, blacklist, blacklist_history, iban_key};
+use crate::db::establish_connection;
+use chrono::Utc;
+use diesel::{prelude::*, sqlite::SqliteConnection};
+use hmac::{Hmac, Mac};
+use rocket::fairing::AdHoc;
+use serde::Deserialize;
+use sha2::Sha256;
+
+// Without keys IBANs are stored as they are. Stored IBANs are hashed with every key in turn,
+// a rotation adds its key at the end.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct PrivacyConfig {
+    pub keys: Vec<String>,
+}
+impl PrivacyConfig {
+    pub fn load() -> Result<PrivacyConfig, String> {
+        config::load::<PrivacyConfig>("privacy")
+    }
+
+    // Fails unless keys are the ones the stored IBANs are hashed with. With other keys every
+    // lookup of a hashed IBAN misses and a blacklisted IBAN would pass.
+    pub fn check(&self, connection: &SqliteConnection) -> Result<(), String> {
+        let applied = iban_key::table
+            .order(iban_key::position.asc())
+            .select(iban_key::fingerprint)
+            .load::<String>(connection)
+            .map_err(|e| e.to_string())?;
+        let configured = self
+            .keys
+            .iter()
+            .map(|key| fingerprint(key))
+            .collect::<Vec<_>>();
+        if applied != configured {
+            return Err(String::from(
+                "Failure: the keys in [privacy] aren't the ones the stored IBANs are hashed with",
+            ));
+        }
+        Ok(())
+    }
+
+    // The value of a rule as it is stored and looked up, value is normalized already
+    pub fn protect(&self, scope: Scope, value: &str) -> String {
+        if scope != Scope::Iban || is_hash(value) {
+            return value.to_string();
+        }
+        self.keys
+            .iter()
+            .fold(value.to_string(), |value, key| hmac(key, &value))
+    }
+
+    // Scope::normalize and protect. A hash marked by exported is taken as it is, as long as there
+    // are keys to have hashed it with.
+    pub fn store(&self, scope: Scope, value: &str) -> Result<String, String> {
+        let value = value.trim();
+        if let Some(hash) = value.strip_prefix(HASH_PREFIX) {
+            if scope != Scope::Iban || self.keys.is_empty() || !is_hash(hash) {
+                return Err(format!(
+                    "Failure: {} is a hashed IBAN, it needs the keys in [privacy] it was hashed with",
+                    value
+                ));
+            }
+            return Ok(hash.to_string());
+        }
+        Ok(self.protect(scope, &scope.normalize(value)?))
+    }
+}
+
+// Checks [privacy] once and keeps it in the managed state, for the requests to pass down.
+// The server doesn't start with keys that don't match the stored IBANs.
+pub fn fairing() -> AdHoc {
+    AdHoc::try_on_ignite("Privacy keys", |rocket| async move {
+        let checked =
+            config::from_figment::<PrivacyConfig>(rocket.figment(), "privacy").and_then(|config| {
+                config.check(&establish_connection())?;
+                Ok(config)
+            });
+        match checked {
+            Ok(config) => Ok(rocket.manage(config)),
+            Err(e) => {
+                eprintln!("Privacy: {}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
+
+// Marks hashed IBANs in exports
+pub const HASH_PREFIX: &str = "hmac:";
+
+// The value of a rule as it is exported, hashed IBANs are marked so an import doesn't hash them again
+pub fn exported(scope: Scope, value: &str) -> String {
+    if scope == Scope::Iban && is_hash(value) {
+        format!("{}{}", HASH_PREFIX, value)
+    } else {
+        value.to_string()
+    }
+}
+
+// Upper case hex, at 64 characters it is longer than any IBAN
+pub fn is_hash(value: &str) -> bool {
+    value.len() == 64
+        && value
+            .chars()
+            .all(|c| c.is_ascii_digit() || ('A'..='F').contains(&c))
+}
+
+fn hmac(key: &str, value: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(value.as_bytes());
+    format!("{:X}", mac.finalize().into_bytes())
+}
+
+// To tell keys apart without storing them
+fn fingerprint(key: &str) -> String {
+    hmac(key, "iban_beaver key fingerprint")
+}
+
+// Hashes the stored IBANs of both lists and their history once more, with key. Afterwards key has
+// to be added at the end of keys, until then lookups of hashed IBANs miss.
+// Fails when keys aren't the ones the stored IBANs are hashed with so far.
+pub fn rotate_key(
+    connection: &SqliteConnection,
+    config: &PrivacyConfig,
+    key: &str,
+) -> Result<usize, String> {
+    if key.is_empty() {
+        return Err(String::from("Failure: the key is empty"));
+    }
+    config.check(connection)?;
+    if config.keys.iter().any(|configured| configured == key) {
+        return Err(String::from("Failure: that key is in use already"));
+    }
+    connection
+        .transaction::<_, diesel::result::Error, _>(|| {
+            let mut rehashed = 0;
+            let rules = blacklist::table
+                .filter(blacklist::scope.eq(Scope::Iban.as_str()))
+                .select((blacklist::id, blacklist::value))
+                .load::<(i32, String)>(connection)?;
+            for (id, value) in rules {
+                rehashed += diesel::update(blacklist::table.find(id))
+                    .set(blacklist::value.eq(hmac(key, &value)))
+                    .execute(connection)?;
+            }
+            let rules = allowlist::table
+                .filter(allowlist::scope.eq(Scope::Iban.as_str()))
+                .select((allowlist::id, allowlist::value))
+                .load::<(i32, String)>(connection)?;
+            for (id, value) in rules {
+                rehashed += diesel::update(allowlist::table.find(id))
+                    .set(allowlist::value.eq(hmac(key, &value)))
+                    .execute(connection)?;
+            }
+            let changes = blacklist_history::table
+                .filter(blacklist_history::scope.eq(Scope::Iban.as_str()))
+                .select((blacklist_history::id, blacklist_history::value))
+                .load::<(i32, String)>(connection)?;
+            for (id, value) in changes {
+                diesel::update(blacklist_history::table.find(id))
+                    .set(blacklist_history::value.eq(hmac(key, &value)))
+                    .execute(connection)?;
+            }
+            diesel::insert_into(iban_key::table)
+                .values((
+                    iban_key::position.eq(config.keys.len() as i32),
+                    iban_key::fingerprint.eq(fingerprint(key)),
+                    iban_key::rotated_at.eq(Utc::now().naive_utc()),
+                ))
+                .execute(connection)?;
+            Ok(rehashed)
+        })
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blacklist::{blacklist, blacklist_status, BlacklistEntry, Subject};
+    use serial_test::serial;
+
+    const IBAN: &str = "DE27100777770209299700";
+
+    #[test]
+    fn protect_ibans() {
+        let plain = PrivacyConfig::default();
+        assert_eq!(plain.protect(Scope::Iban, IBAN), IBAN);
+        let one = PrivacyConfig {
+            keys: vec![String::from("first")],
+        };
+        let two = PrivacyConfig {
+            keys: vec![String::from("first"), String::from("second")],
+        };
+        let hashed = one.protect(Scope::Iban, IBAN);
+        assert!(is_hash(&hashed));
+        assert_ne!(hashed, two.protect(Scope::Iban, IBAN));
+        // a rotation hashes what is stored with the new key
+        assert_eq!(hmac("second", &hashed), two.protect(Scope::Iban, IBAN));
+        // already hashed, or not a single IBAN
+        assert_eq!(two.protect(Scope::Iban, &hashed), hashed);
+        assert_eq!(one.protect(Scope::Bank, "DE10077777"), "DE10077777");
+
+        assert_eq!(
+            one.store(Scope::Iban, "de27 1007 7777 0209 2997 00"),
+            Ok(hashed.clone())
+        );
+        let marked = exported(Scope::Iban, &hashed);
+        assert_eq!(marked, format!("hmac:{}", hashed));
+        assert_eq!(one.store(Scope::Iban, &marked), Ok(hashed.clone()));
+        // unmarked it is taken for an IBAN, which it isn't
+        assert!(one.store(Scope::Iban, &hashed).is_err());
+        assert!(plain.store(Scope::Iban, &marked).is_err());
+        assert!(one.store(Scope::Bank, &marked).is_err());
+        assert_eq!(exported(Scope::Iban, IBAN), IBAN);
+        assert!(one.store(Scope::Iban, "DE27100777770209299704").is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn rotation() {
+        let connection = &establish_connection();
+        let one = PrivacyConfig {
+            keys: vec![String::from("first")],
+        };
+        // rolled back, the test db keeps its plain text IBANs
+        connection.test_transaction::<_, String, _>(|| {
+            let entry = BlacklistEntry::default();
+            blacklist(
+                connection,
+                &PrivacyConfig::default(),
+                "",
+                Scope::Iban,
+                IBAN,
+                "add",
+                &entry,
+            )
+            .map_err(|e| e.to_string())?;
+            assert!(rotate_key(connection, &one, "second").is_err());
+            assert!(rotate_key(connection, &PrivacyConfig::default(), "first")? > 0);
+            assert!(one.check(connection).is_ok());
+            // the config without keys no longer matches, the server wouldn't start with it
+            assert!(PrivacyConfig::default().check(connection).is_err());
+            let subject = Subject {
+                iban: IBAN,
+                bank_code: None,
+                bic: None,
+            };
+            assert!(blacklist_status(connection, &one, "", &subject)?.listed);
+            assert!(rotate_key(connection, &one, "first").is_err());
+            assert!(rotate_key(connection, &PrivacyConfig::default(), "second").is_err());
+            let hashed = one.protect(Scope::Iban, IBAN);
+            let stored = blacklist::table
+                .filter(blacklist::value.eq(&hashed))
+                .count()
+                .get_result::<i64>(connection)
+                .map_err(|e| e.to_string())?;
+            assert_eq!(stored, 1);
+            Ok(())
+        });
+    }
+}
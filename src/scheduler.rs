@@ -0,0 +1,193 @@
+// Background refresh of the country tables, configured in the [scheduler] section of Rocket.toml
+use crate::config;
+use crate::country::get_country;
+use crate::db::{download_table, establish_connection, record_refresh, RefreshError};
+use chrono::Utc;
+use cron::Schedule;
+use rand::Rng;
+use rocket::fairing::AdHoc;
+use rocket::figment::Figment;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct SchedulerConfig {
+    pub enabled: bool,
+    // random delay added to every run so we don't hit the publishers on the exact minute
+    pub jitter_secs: u64,
+    // runs whose source file came in but didn't load are tried again this often, downloads
+    // have retries of their own (see [download])
+    pub retries: u32,
+    // doubled after every failed attempt
+    pub retry_delay_secs: u64,
+    // a table that hasn't been refreshed successfully for this long is reported as stale
+    pub max_age_hours: i64,
+    // country code -> cron expression (sec min hour day-of-month month day-of-week)
+    pub countries: HashMap<String, String>,
+}
+impl Default for SchedulerConfig {
+    fn default() -> SchedulerConfig {
+        SchedulerConfig {
+            enabled: false,
+            jitter_secs: 600,
+            retries: 2,
+            retry_delay_secs: 300,
+            max_age_hours: 720,
+            countries: HashMap::new(),
+        }
+    }
+}
+impl SchedulerConfig {
+    pub fn from_figment(figment: &Figment) -> Result<SchedulerConfig, String> {
+        config::from_figment(figment, "scheduler")
+    }
+
+    pub fn load() -> Result<SchedulerConfig, String> {
+        config::load("scheduler")
+    }
+
+    pub fn schedules(&self) -> Result<Vec<(String, Schedule)>, String> {
+        self.countries
+            .iter()
+            .map(|(country_code, expression)| {
+                get_country(country_code)?;
+                let schedule = Schedule::from_str(expression).map_err(|e| {
+                    format!(
+                        "Failure: bad schedule '{}' for {}: {}",
+                        expression, country_code, e
+                    )
+                })?;
+                Ok((country_code.to_uppercase(), schedule))
+            })
+            .collect()
+    }
+}
+
+pub fn fairing() -> AdHoc {
+    AdHoc::try_on_ignite("Refresh scheduler", |rocket| async move {
+        let started = SchedulerConfig::from_figment(rocket.figment()).and_then(|config| {
+            if config.enabled {
+                start(config)?;
+            }
+            Ok(())
+        });
+        match started {
+            Ok(_) => Ok(rocket),
+            Err(e) => {
+                eprintln!("Scheduler: {}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
+
+// Spawns one thread per scheduled country. Threads sleep until their next run and never return.
+pub fn start(config: SchedulerConfig) -> Result<(), String> {
+    for (country_code, schedule) in config.schedules()? {
+        let config = config.clone();
+        thread::spawn(move || loop {
+            let next = match schedule.upcoming(Utc).next() {
+                Some(next) => next,
+                None => return, // schedule has run out, e.g. a fixed year in the past
+            };
+            let jitter = rand::thread_rng().gen_range(0..=config.jitter_secs);
+            let wait = (next - Utc::now()).to_std().unwrap_or_default();
+            thread::sleep(wait + Duration::from_secs(jitter));
+            run(&config, &country_code);
+        });
+    }
+    Ok(())
+}
+
+// A run that failed to load is tried again after a while, the publisher may have served a
+// broken file. A failed download isn't, the fetcher retried it already. However often it tried,
+// a failed run counts as one failure in the refresh table.
+fn run(config: &SchedulerConfig, country_code: &str) {
+    let connection = establish_connection();
+    let mut delay = Duration::from_secs(config.retry_delay_secs);
+    let mut attempt = 0;
+    let result = loop {
+        match download_table(&connection, country_code) {
+            Err(RefreshError::Load(e)) if attempt < config.retries => {
+                eprintln!(
+                    "Scheduler: load of {} failed (attempt {}): {}",
+                    country_code,
+                    attempt + 1,
+                    e
+                );
+                thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            result => break result.map_err(String::from),
+        }
+    };
+    if let Err(e) = record_refresh(
+        &connection,
+        country_code,
+        result.as_ref().map(|_| ()).map_err(String::clone),
+    ) {
+        eprintln!(
+            "Scheduler: cannot record the refresh of {}: {}",
+            country_code, e
+        );
+    }
+    match result {
+        Ok(Some(load)) => println!(
+            "Scheduler: refreshed {} with {} rows, {} rejected",
+            country_code, load.report.rows_loaded, load.report.rows_rejected
+        ),
+        Ok(None) => println!("Scheduler: {} is unchanged", country_code),
+        Err(e) => eprintln!("Scheduler: refresh of {} failed: {}", country_code, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::figment::providers::{Format, Toml};
+
+    fn config(toml: &str) -> Result<SchedulerConfig, String> {
+        SchedulerConfig::from_figment(&Figment::from(Toml::string(toml)))
+    }
+
+    #[test]
+    fn missing_section_is_disabled() {
+        let config = config("").unwrap();
+        assert!(!config.enabled);
+        assert!(config.schedules().unwrap().is_empty());
+    }
+
+    #[test]
+    fn schedules() {
+        let ok = config(
+            r#"
+            [scheduler]
+            enabled = true
+            jitter_secs = 60
+            retries = 1
+            [scheduler.countries]
+            de = "0 0 3 * * Mon"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(ok.jitter_secs, 60);
+        assert_eq!(ok.retries, 1);
+        assert_eq!(
+            ok.retry_delay_secs,
+            SchedulerConfig::default().retry_delay_secs
+        );
+        assert_eq!(ok.max_age_hours, SchedulerConfig::default().max_age_hours);
+        let schedules = ok.schedules().unwrap();
+        assert_eq!(schedules[0].0, "DE");
+
+        let bad_cron = config("[scheduler.countries]\nDE = \"every monday\"").unwrap();
+        assert!(bad_cron.schedules().is_err());
+        let bad_country = config("[scheduler.countries]\nXX = \"0 0 3 * * *\"").unwrap();
+        assert!(bad_country.schedules().is_err());
+    }
+}
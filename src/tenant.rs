@@ -0,0 +1,233 @@
+// Teams that share a server each get their own blacklist, allowlist and countries.
+// A request names its tenant with an X-Api-Key header, or with an X-Tenant header for tenants
+// without an api key. Requests without either belong to the default tenant, which has the rules
+// from before there were tenants.
+use crate::allowlist::Mode;
+use crate::config;
+use rocket::fairing::AdHoc;
+use rocket::figment::Figment;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::okapi::openapi3::{
+    Object, SecurityRequirement, SecurityScheme, SecuritySchemeData,
+};
+use rocket_okapi::request::{OpenApiFromRequest, RequestHeaderInput};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+pub const API_KEY_HEADER: &str = "X-Api-Key";
+pub const TENANT_HEADER: &str = "X-Tenant";
+
+// [default.tenants.<name>] in Rocket.toml
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct TenantConfig {
+    // without one the X-Tenant header is enough
+    pub api_key: Option<String>,
+    // the countries verify looks banks up for, every supported country when empty
+    pub countries: Vec<String>,
+    // mode of [allowlist] when missing
+    pub mode: Option<Mode>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Tenant {
+    // empty for the default tenant
+    pub name: String,
+    pub config: TenantConfig,
+}
+impl Tenant {
+    pub fn country_enabled(&self, country_code: &str) -> bool {
+        self.config.countries.is_empty()
+            || self
+                .config
+                .countries
+                .iter()
+                .any(|country| country.eq_ignore_ascii_case(country_code))
+    }
+}
+
+// [tenants] from the config, two tenants with the same api key would be told apart by chance
+pub fn tenants(figment: &Figment) -> Result<HashMap<String, TenantConfig>, String> {
+    let tenants = config::from_figment::<HashMap<String, TenantConfig>>(figment, "tenants")?;
+    let mut names = tenants
+        .iter()
+        .filter(|(_, config)| config.api_key.is_some())
+        .map(|(name, config)| (config.api_key.as_deref(), name))
+        .collect::<Vec<_>>();
+    names.sort();
+    if let Some(pair) = names.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+        return Err(format!(
+            "Failure: bad [tenants] config: {} and {} have the same api key",
+            pair[0].1, pair[1].1
+        ));
+    }
+    Ok(tenants)
+}
+
+// [tenants] as it was loaded at start, in the managed state
+pub struct Tenants(pub HashMap<String, TenantConfig>);
+
+// Loads [tenants] once for the requests to identify their tenant with.
+// The server doesn't start with a config that tenants() rejects.
+pub fn fairing() -> AdHoc {
+    AdHoc::try_on_ignite("Tenants", |rocket| async move {
+        match tenants(rocket.figment()) {
+            Ok(tenants) => Ok(rocket.manage(Tenants(tenants))),
+            Err(e) => {
+                eprintln!("Tenants: {}", e);
+                Err(rocket)
+            }
+        }
+    })
+}
+
+// Why the tenant of a request wasn't accepted, for the 401 catcher
+pub fn rejection(request: &Request<'_>) -> Option<String> {
+    request.local_cache(|| Rejection(None)).0.clone()
+}
+
+struct Rejection(Option<String>);
+
+// The tenant a request belongs to, by api key first and by name second
+pub fn identify(
+    tenants: &HashMap<String, TenantConfig>,
+    api_key: Option<&str>,
+    name: Option<&str>,
+) -> Result<Tenant, String> {
+    let tenant = |(name, config): (&String, &TenantConfig)| Tenant {
+        name: name.clone(),
+        config: config.clone(),
+    };
+    match (api_key, name) {
+        (Some(api_key), _) => tenants
+            .iter()
+            .find(|(_, config)| {
+                config
+                    .api_key
+                    .as_deref()
+                    .is_some_and(|key| same_key(key, api_key))
+            })
+            .map(tenant)
+            .ok_or_else(|| String::from("Failure: unknown api key")),
+        (None, Some(name)) => match tenants.get_key_value(name) {
+            Some((_, config)) if config.api_key.is_some() => {
+                Err(format!("Failure: tenant {} needs its api key", name))
+            }
+            Some(found) => Ok(tenant(found)),
+            None => Err(format!("Failure: unknown tenant {}", name)),
+        },
+        (None, None) => Ok(Tenant::default()),
+    }
+}
+
+// Compares the digests byte by byte to the end, so how long it takes doesn't tell how much of
+// a guessed key was right
+fn same_key(key: &str, guess: &str) -> bool {
+    let (key, guess) = (
+        Sha256::digest(key.as_bytes()),
+        Sha256::digest(guess.as_bytes()),
+    );
+    key.iter()
+        .zip(guess.iter())
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Tenant {
+    type Error = String;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Tenant, String> {
+        let headers = request.headers();
+        let identified = match request.rocket().state::<Tenants>() {
+            Some(Tenants(tenants)) => identify(
+                tenants,
+                headers.get_one(API_KEY_HEADER),
+                headers.get_one(TENANT_HEADER),
+            ),
+            None => Err(String::from("Failure: the tenants fairing isn't attached")),
+        };
+        match identified {
+            Ok(tenant) => Outcome::Success(tenant),
+            Err(e) => {
+                request.local_cache(|| Rejection(Some(e.clone())));
+                Outcome::Failure((Status::Unauthorized, e))
+            }
+        }
+    }
+}
+
+impl<'r> OpenApiFromRequest<'r> for Tenant {
+    fn from_request_input(
+        _gen: &mut OpenApiGenerator,
+        _name: String,
+        _required: bool,
+    ) -> rocket_okapi::Result<RequestHeaderInput> {
+        let scheme = SecurityScheme {
+            description: Some(format!(
+                "The tenant, or {} for tenants without an api key",
+                TENANT_HEADER
+            )),
+            data: SecuritySchemeData::ApiKey {
+                name: String::from(API_KEY_HEADER),
+                location: String::from("header"),
+            },
+            extensions: Object::default(),
+        };
+        let mut requirement = SecurityRequirement::new();
+        requirement.insert(String::from("tenant"), Vec::new());
+        Ok(RequestHeaderInput::Security(
+            String::from("tenant"),
+            scheme,
+            requirement,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::figment::providers::{Format, Toml};
+
+    #[test]
+    fn identify_tenants() {
+        let mut tenants = HashMap::new();
+        tenants.insert(
+            String::from("payments"),
+            TenantConfig {
+                api_key: Some(String::from("secret")),
+                countries: vec![String::from("DE")],
+                mode: None,
+            },
+        );
+        tenants.insert(String::from("payroll"), TenantConfig::default());
+        assert_eq!(identify(&tenants, None, None), Ok(Tenant::default()));
+        let payments = identify(&tenants, Some("secret"), Some("payroll")).unwrap();
+        assert_eq!(payments.name, "payments");
+        assert!(payments.country_enabled("de"));
+        assert!(!payments.country_enabled("AT"));
+        assert!(identify(&tenants, Some("guess"), None).is_err());
+        assert!(identify(&tenants, Some("secret "), None).is_err());
+        assert!(identify(&tenants, Some("secre"), None).is_err());
+        assert!(identify(&tenants, None, Some("payments")).is_err());
+        assert!(identify(&tenants, None, Some("marketing")).is_err());
+        let payroll = identify(&tenants, None, Some("payroll")).unwrap();
+        assert_eq!(payroll.name, "payroll");
+        assert!(payroll.country_enabled("AT"));
+    }
+
+    #[test]
+    fn shared_api_key() {
+        let config = |tenants: &str| Figment::from(Toml::string(tenants));
+        let ok = config("[tenants]\npayments = { api_key = \"secret\" }\npayroll = {}\nhr = {}");
+        assert_eq!(tenants(&ok).unwrap().len(), 3);
+        let shared = config(
+            "[tenants]\npayments = { api_key = \"secret\" }\npayroll = { api_key = \"secret\" }",
+        );
+        let e = tenants(&shared).unwrap_err();
+        assert!(e.contains("payments and payroll have the same api key"));
+    }
+}